@@ -0,0 +1,296 @@
+// Headless runner for SandBoard. Builds a board from a named scenario, ticks
+// it without a window or GPU, and writes stats and a final snapshot to disk.
+//
+// usage: sand_sim [--scenario NAME | --load FILE | --image FILE | --replay FILE] [--ticks N] [--seed N]
+//                 [--width N] [--height N] [--stats-every N] [--scale N]
+//                 [--elements FILE] [--threads N] [--no-floor] [--edge SIDE=MODE]
+//                 [--out DIR]
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use sand_game::falling_sand::*;
+use sand_game::falling_sand::element_registry::ElementRegistry;
+use sand_game::falling_sand::edges::EdgeMode;
+use sand_game::falling_sand::replay::Recording;
+
+
+struct SimOptions {
+    scenario: String,
+    load: Option<PathBuf>,
+    image: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    elements: Option<PathBuf>,
+    ticks: usize,
+    seed: u64,
+    width: usize,
+    height: usize,
+    stats_every: usize,
+    scale: u32,
+    threads: usize,
+    // (side, mode) as given, the modes can name elements so they're looked
+    // up once the definitions are loaded
+    edges: Vec<(String, String)>,
+    out_dir: PathBuf,
+}
+
+impl SimOptions {
+    fn new() -> Self {
+        Self {
+            scenario: "boring_ocean".to_string(),
+            load: None,
+            image: None,
+            replay: None,
+            elements: None,
+            ticks: 1000,
+            seed: 0,
+            width: 200,
+            height: 200,
+            stats_every: 100,
+            scale: 1,
+            threads: 1,
+            edges: Vec::new(),
+            out_dir: PathBuf::from("sim_output"),
+        }
+    }
+
+    fn from_args(args: &[String]) -> Result<Self> {
+        let mut options = Self::new();
+        let mut ticks_given = false;
+        let mut i = 0;
+        while i < args.len() {
+            let flag = args[i].as_str();
+            if flag == "--no-floor" {
+                options.edges.push(("bottom".to_string(), "void".to_string()));
+                i += 1;
+                continue
+            }
+            if flag == "--help" || flag == "-h" {
+                print_usage();
+                std::process::exit(0);
+            }
+
+            let value = args.get(i + 1).ok_or_else(|| anyhow!("missing value for {}", flag))?;
+            match flag {
+                "--scenario" => options.scenario = value.clone(),
+                "--load" => options.load = Some(PathBuf::from(value)),
+                "--image" => options.image = Some(PathBuf::from(value)),
+                "--replay" => options.replay = Some(PathBuf::from(value)),
+                "--elements" => options.elements = Some(PathBuf::from(value)),
+                "--ticks" => {
+                    options.ticks = parse_number(flag, value)?;
+                    ticks_given = true;
+                },
+                "--seed" => options.seed = parse_number(flag, value)?,
+                "--width" => options.width = parse_number(flag, value)?,
+                "--height" => options.height = parse_number(flag, value)?,
+                "--stats-every" => options.stats_every = parse_number(flag, value)?,
+                "--scale" => options.scale = parse_number(flag, value)?,
+                "--threads" => options.threads = parse_number(flag, value)?,
+                "--edge" => {
+                    let (side, mode) = value.split_once('=').ok_or_else(|| anyhow!("--edge expects SIDE=MODE, got '{}'", value))?;
+                    if !["top", "bottom", "left", "right"].contains(&side) {
+                        bail!("--edge side must be top, bottom, left or right, got '{}'", side);
+                    }
+                    options.edges.push((side.to_string(), mode.to_string()));
+                },
+                "--out" => options.out_dir = PathBuf::from(value),
+                _ => bail!("unknown argument {}", flag),
+            }
+            i += 2;
+        }
+
+        if options.width == 0 || options.height == 0 {
+            bail!("board size must be at least 1x1");
+        }
+        if options.load.is_some() && options.image.is_some() {
+            bail!("--load and --image can't be used together");
+        }
+        if options.replay.is_some() && (options.load.is_some() || options.image.is_some()) {
+            bail!("--replay can't be used with --load or --image, the recording has its own board");
+        }
+        if options.replay.is_some() && ticks_given {
+            bail!("--ticks can't be used with --replay, a replay runs as long as its recording");
+        }
+        if options.scale == 0 {
+            bail!("--scale must be at least 1");
+        }
+        if options.threads == 0 {
+            bail!("--threads must be at least 1");
+        }
+        if options.stats_every == 0 {
+            bail!("--stats-every must be at least 1");
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T> {
+    value.parse::<T>().map_err(|_| anyhow!("{} expects a number, got '{}'", flag, value))
+}
+
+fn set_edges(sand_board: &mut SandBoard, edge_options: &[(String, String)]) -> Result<()> {
+    let mut edges = sand_board.edges();
+    for (side, mode) in edge_options {
+        let edge = EdgeMode::from_name(mode, sand_board.registry())
+            .ok_or_else(|| anyhow!("unknown edge mode '{}', expected solid, void, wrap or source:ELEMENT", mode))?;
+        match side.as_str() {
+            "top" => edges.top = edge,
+            "bottom" => edges.bottom = edge,
+            "left" => edges.left = edge,
+            _ => edges.right = edge,
+        }
+    }
+    sand_board.set_edges(edges).context("couldn't set the edges")?;
+    Ok(())
+}
+
+fn print_usage() {
+    println!("usage: sand_sim [--scenario NAME | --load FILE | --image FILE | --replay FILE] [--ticks N] [--seed N]");
+    println!("                [--width N] [--height N] [--stats-every N] [--scale N] [--elements FILE]");
+    println!("                [--threads N] [--no-floor] [--edge SIDE=MODE] [--out DIR]");
+    println!("--load starts from a saved board, keeping its size, edges and seed");
+    println!("--image starts from a picture, one cell per pixel, using the game's colors");
+    println!("--replay plays back a session recorded in the game (F6) for as many ticks as it was");
+    println!("         recorded, and fails if the board stops matching the recording");
+    println!("--threads spreads each tick over N threads, the result doesn't depend on it");
+    println!("--elements loads element definitions, see assets/elements.example.txt");
+    println!("--edge sets the top, bottom, left or right edge to solid, void, wrap or source:ELEMENT,");
+    println!("       --no-floor is the same as --edge bottom=void");
+    println!("scenarios: {}", SCENARIO_NAMES.join(", "));
+}
+
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut options = SimOptions::from_args(&args)?;
+
+    let registry = match &options.elements {
+        Some(path) => ElementRegistry::from_file(path)
+            .with_context(|| format!("couldn't load element definitions from {}", path.display()))?,
+        None => ElementRegistry::default(),
+    };
+    let registry = Arc::new(registry);
+
+    let recording = match &options.replay {
+        Some(path) => {
+            let file = File::open(path).with_context(|| format!("couldn't open {}", path.display()))?;
+            let recording = Recording::load(BufReader::new(file))
+                .with_context(|| format!("couldn't load {}", path.display()))?;
+            options.ticks = recording.ticks();
+            Some(recording)
+        },
+        None => None,
+    };
+
+    let mut sand_board = match (&recording, &options.load, &options.image) {
+        (Some(recording), _, _) => {
+            recording.load_board(registry).context("couldn't load the recording's board")?
+        },
+        (None, Some(path), _) => {
+            let file = File::open(path).with_context(|| format!("couldn't open {}", path.display()))?;
+            SandBoard::load_with_registry(BufReader::new(file), registry)
+                .with_context(|| format!("couldn't load {}", path.display()))?
+        },
+        (None, None, Some(path)) => {
+            let (mut sand_board, report) = SandBoard::from_image_with_registry(path, registry)
+                .with_context(|| format!("couldn't import {}", path.display()))?;
+            for unmapped in report.unmapped_colors.iter() {
+                log::warn!("{} pixels of color {:02X?} didn't match any element (nearest is {:?}), left empty",
+                    unmapped.pixel_count, unmapped.color, unmapped.nearest);
+            }
+            sand_board.reseed(options.seed);
+            set_edges(&mut sand_board, &options.edges)?;
+            sand_board
+        },
+        (None, None, None) => {
+            let mut sand_board = SandBoard::with_seed(options.width, options.height, options.seed);
+            sand_board.set_registry(registry);
+            set_edges(&mut sand_board, &options.edges)?;
+            if !sand_board.apply_scenario(&options.scenario) {
+                bail!("unknown scenario '{}', expected one of: {}", options.scenario, SCENARIO_NAMES.join(", "));
+            }
+            sand_board
+        }
+    };
+
+    sand_board.set_threads(options.threads);
+
+    fs::create_dir_all(&options.out_dir)
+        .with_context(|| format!("couldn't create output directory {}", options.out_dir.display()))?;
+
+    let stats_path = options.out_dir.join("stats.csv");
+    let mut stats = BufWriter::new(File::create(&stats_path)
+        .with_context(|| format!("couldn't create {}", stats_path.display()))?);
+    writeln!(stats, "tick,granules,awake_chunks")?;
+    writeln!(stats, "0,{},{}", sand_board.get_granule_count(), sand_board.awake_chunk_count())?;
+
+    let mut replay = recording.as_ref().map(|recording| recording.replay());
+    let start = Instant::now();
+    for tick in 1..=options.ticks {
+        match replay.as_mut() {
+            Some(replay) => replay.step(&mut sand_board)?,
+            None => sand_board.tick(),
+        }
+        if tick % options.stats_every == 0 || tick == options.ticks {
+            writeln!(stats, "{},{},{}", tick, sand_board.get_granule_count(), sand_board.awake_chunk_count())?;
+        }
+    }
+    if let Some(replay) = replay.as_mut() {
+        replay.finish(&mut sand_board)?;
+    }
+    let elapsed = start.elapsed();
+    stats.flush()?;
+
+    write_summary(&options.out_dir.join("summary.txt"), &options, &sand_board, elapsed.as_secs_f64())?;
+    let snapshot_path = options.out_dir.join("snapshot.png");
+    sand_board.output_texture().save_png(&snapshot_path, options.scale)
+        .with_context(|| format!("couldn't write {}", snapshot_path.display()))?;
+    let save_path = options.out_dir.join("final.sand");
+    let save_file = File::create(&save_path).with_context(|| format!("couldn't create {}", save_path.display()))?;
+    sand_board.save(BufWriter::new(save_file))?;
+
+    println!("ran {} ticks ({}x{}, seed {}) in {:.2}s, output in {}",
+        options.ticks, sand_board.width, sand_board.height, sand_board.seed(),
+        elapsed.as_secs_f64(), options.out_dir.display());
+    Ok(())
+}
+
+
+fn write_summary(path: &Path, options: &SimOptions, sand_board: &SandBoard, seconds: f64) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)
+        .with_context(|| format!("couldn't create {}", path.display()))?);
+    match (&options.replay, &options.load, &options.image) {
+        (Some(path), _, _) => writeln!(file, "replay: {}", path.display())?,
+        (None, Some(path), _) => writeln!(file, "loaded: {}", path.display())?,
+        (None, None, Some(path)) => writeln!(file, "image: {}", path.display())?,
+        (None, None, None) => writeln!(file, "scenario: {}", options.scenario)?,
+    }
+    if let Some(path) = &options.elements {
+        writeln!(file, "elements: {}", path.display())?;
+    }
+    writeln!(file, "seed: {}", sand_board.seed())?;
+    writeln!(file, "size: {}x{}", sand_board.width, sand_board.height)?;
+    writeln!(file, "edges: {:?}", sand_board.edges())?;
+    writeln!(file, "ticks: {}", options.ticks)?;
+    writeln!(file, "threads: {}", sand_board.threads())?;
+    writeln!(file, "seconds: {:.3}", seconds)?;
+    if seconds > 0.0 {
+        writeln!(file, "ticks_per_second: {:.1}", options.ticks as f64 / seconds)?;
+    }
+    writeln!(file, "granules: {}", sand_board.get_granule_count())?;
+    for (element_type, count) in sand_board.get_element_counts() {
+        let name = sand_board.registry().get(element_type)
+            .map_or(format!("{:?}", element_type), |definition| definition.name.clone());
+        writeln!(file, "  {}: {}", name, count)?;
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// The board's cells. Mostly this is a Vec<Element>, but chunks that update
+// at the same time (see tick_chunks) each get a board of their own that works
+// directly on the same cells, through a view.
+//
+// A view only ever hands out one cell at a time, or the short runs asked for
+// by indexing with a range, never the whole board, so views on other threads
+// are only ever touching their own cells. Keeping to disjoint cells is up to
+// whoever makes the views; chunks updated together are a whole chunk apart
+// and nothing reaches further than MAX_REACH.
+
+use std::ops::{Index, IndexMut, Range};
+
+use super::elements::Element;
+
+
+pub(crate) struct Cells {
+    // the cells themselves, left empty in a view
+    owned: Vec<Element>,
+    start: *mut Element,
+    len: usize,
+}
+
+// SAFETY: a Cells that owns its cells is as good as a Vec, and a shared
+// reference to one only reads. A view is only sent to another thread by
+// tick_chunks, which keeps the cells each view touches apart from every other
+// view's and waits for them all to finish before touching the board again.
+unsafe impl Send for Cells {}
+unsafe impl Sync for Cells {}
+
+impl Cells {
+    pub fn new(mut owned: Vec<Element>) -> Self {
+        let start = owned.as_mut_ptr();
+        let len = owned.len();
+        Self { owned, start, len }
+    }
+
+    // Another handle on the same cells. It mustn't outlive them, and nothing
+    // else may use the cells a view is working on while it does.
+    pub fn view(&mut self) -> Self {
+        Self { owned: Vec::new(), start: self.start, len: self.len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.check(a);
+        self.check(b);
+        // SAFETY: both are in bounds, and ptr::swap allows them to be the same
+        unsafe { std::ptr::swap(self.start.add(a), self.start.add(b)) }
+    }
+
+    // The whole board, so not for use in a view.
+    pub fn iter(&self) -> std::slice::Iter<'_, Element> {
+        self.as_slice().iter()
+    }
+
+    pub fn as_slice(&self) -> &[Element] {
+        debug_assert!(self.is_owner(), "a view of the board's cells can't borrow all of them");
+        &self[0..self.len]
+    }
+
+    fn is_owner(&self) -> bool {
+        self.owned.len() == self.len
+    }
+
+    fn check(&self, index: usize) {
+        assert!(index < self.len, "index out of bounds: the len is {} but the index is {}", self.len, index);
+    }
+}
+
+impl Index<usize> for Cells {
+    type Output = Element;
+
+    fn index(&self, index: usize) -> &Element {
+        self.check(index);
+        // SAFETY: in bounds, and see Cells for who else may be using it
+        unsafe { &*self.start.add(index) }
+    }
+}
+
+impl IndexMut<usize> for Cells {
+    fn index_mut(&mut self, index: usize) -> &mut Element {
+        self.check(index);
+        // SAFETY: in bounds, and see Cells for who else may be using it
+        unsafe { &mut *self.start.add(index) }
+    }
+}
+
+impl Index<Range<usize>> for Cells {
+    type Output = [Element];
+
+    fn index(&self, range: Range<usize>) -> &[Element] {
+        assert!(range.start <= range.end && range.end <= self.len, "range {:?} out of bounds for {} cells", range, self.len);
+        // SAFETY: in bounds, and see Cells for who else may be using it
+        unsafe { std::slice::from_raw_parts(self.start.add(range.start), range.len()) }
+    }
+}
+
+impl Clone for Cells {
+    fn clone(&self) -> Self {
+        Self::new(self.as_slice().to_vec())
+    }
+}
+
+impl PartialEq for Cells {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
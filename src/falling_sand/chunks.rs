@@ -0,0 +1,440 @@
+// The board is split into CHUNK_SIZE x CHUNK_SIZE chunks so tick only visits
+// the parts of the board where something is happening. A chunk is awake when
+// a cell in it or in one of its neighbours changed last tick, or when it holds
+// something living that can act on its own (see LIVING_ELEMENTS). Sleeping
+// chunks are skipped entirely.
+//
+// Changes are found by comparing the cells around the awake chunks before
+// and after the tick, rather than by marking them in every update function.
+// Nothing an update does reaches further than MAX_REACH, so the awake chunks
+// and their neighbours are the only cells that can change.
+//
+// The awake chunks are updated in place in a four phase checkerboard, which
+// lets the chunks of each phase run on separate threads (see
+// SandBoard::set_threads). Across a wrapping edge the first and last columns
+// (or rows) are neighbours too, so one of them waits for the rest of its
+// phase and then runs on its own (see is_seam).
+
+use rand::Rng;
+
+use super::SandBoard;
+use super::edges::Edges;
+use super::elements::{Element, ElementType};
+
+pub const CHUNK_SIZE: usize = 32;
+
+// How far from its own cell an update may look or move things. Chunks that
+// update at the same time are a whole chunk apart, so as long as nothing
+// reaches further than half a chunk they can't touch the same cells.
+pub const MAX_REACH: usize = CHUNK_SIZE / 2;
+
+
+// What a chunk did last tick, for the debug overlay and for stats.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct ChunkActivity {
+    // top left cell and size in cells, chunks on the right and bottom edges
+    // can be smaller than CHUNK_SIZE
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub is_awake: bool,
+    pub has_changed: bool,
+}
+
+
+pub(crate) struct ChunkMap {
+    columns: usize,
+    rows: usize,
+    // chunks to update next tick, and the ones updated last tick
+    awake: Vec<bool>,
+    was_awake: Vec<bool>,
+    has_changed: Vec<bool>,
+    // the watched cells as they were at the start of the tick, kept around
+    // so the buffer isn't reallocated every tick
+    snapshot: Vec<Element>,
+    // changing an edge wakes the whole board
+    last_edges: Edges,
+    // whether chunks on opposite edges are neighbours
+    wrap_columns: bool,
+    wrap_rows: bool,
+}
+
+impl ChunkMap {
+    pub fn new(width: usize, height: usize, edges: Edges) -> Self {
+        let columns = width.div_ceil(CHUNK_SIZE);
+        let rows = height.div_ceil(CHUNK_SIZE);
+        Self {
+            columns,
+            rows,
+            awake: vec![true; columns * rows],
+            was_awake: vec![false; columns * rows],
+            has_changed: vec![false; columns * rows],
+            snapshot: Vec::new(),
+            last_edges: edges,
+            wrap_columns: false,
+            wrap_rows: false,
+        }
+    }
+
+    pub fn wake_all(&mut self) {
+        self.awake.fill(true);
+    }
+
+    fn neighbours(&self, chunk: usize) -> impl Iterator<Item = usize> {
+        let columns = self.columns as i64;
+        let rows = self.rows as i64;
+        let column = (chunk % self.columns) as i64;
+        let row = (chunk / self.columns) as i64;
+        let (wrap_columns, wrap_rows) = (self.wrap_columns, self.wrap_rows);
+        (-1..=1).flat_map(move |k| (-1..=1).map(move |i| (column + i, row + k)))
+            .map(move |(x, y)| {
+                let x = if wrap_columns { x.rem_euclid(columns) } else { x };
+                let y = if wrap_rows { y.rem_euclid(rows) } else { y };
+                (x, y)
+            })
+            .filter(move |(x, y)| *x >= 0 && *x < columns && *y >= 0 && *y < rows)
+            .map(move |(x, y)| (y * columns + x) as usize)
+    }
+}
+
+
+impl SandBoard {
+    // Cell bounds of a chunk, as (x_start, y_start, x_end, y_end) with the
+    // ends exclusive.
+    pub(super) fn chunk_bounds(&self, chunk: usize) -> (usize, usize, usize, usize) {
+        let x_start = (chunk % self.chunks.columns) * CHUNK_SIZE;
+        let y_start = (chunk / self.chunks.columns) * CHUNK_SIZE;
+        let x_end = (x_start + CHUNK_SIZE).min(self.width);
+        let y_end = (y_start + CHUNK_SIZE).min(self.height);
+        (x_start, y_start, x_end, y_end)
+    }
+
+    // Makes every chunk update next tick. Anything that rewrites the board
+    // outside of tick has to call this or wake_region.
+    pub fn wake_all(&mut self) {
+        self.chunks.wake_all();
+    }
+
+    // Wakes the chunks overlapping the given cells, plus their neighbours.
+    pub fn wake_region(&mut self, x_start: usize, y_start: usize, x_end: usize, y_end: usize) {
+        if self.width == 0 || self.height == 0 {
+            return
+        }
+        let column_start = x_start.min(self.width - 1) / CHUNK_SIZE;
+        let column_end = x_end.min(self.width - 1) / CHUNK_SIZE;
+        let row_start = y_start.min(self.height - 1) / CHUNK_SIZE;
+        let row_end = y_end.min(self.height - 1) / CHUNK_SIZE;
+        for row in row_start..=row_end {
+            for column in column_start..=column_end {
+                let neighbours: Vec<usize> = self.chunks.neighbours(row * self.chunks.columns + column).collect();
+                for neighbour in neighbours {
+                    self.chunks.awake[neighbour] = true;
+                }
+            }
+        }
+    }
+
+    pub fn chunk_activity(&self) -> Vec<ChunkActivity> {
+        let mut activity = Vec::with_capacity(self.chunks.awake.len());
+        for chunk in 0..self.chunks.awake.len() {
+            let (x_start, y_start, x_end, y_end) = self.chunk_bounds(chunk);
+            activity.push(ChunkActivity {
+                x: x_start,
+                y: y_start,
+                width: x_end - x_start,
+                height: y_end - y_start,
+                is_awake: self.chunks.was_awake[chunk],
+                has_changed: self.chunks.has_changed[chunk],
+            });
+        }
+        activity
+    }
+
+    // Number of chunks that were updated last tick.
+    pub fn awake_chunk_count(&self) -> usize {
+        self.chunks.was_awake.iter().filter(|is_awake| **is_awake).count()
+    }
+
+    // Runs one tick over the awake chunks, in four phases of a checkerboard:
+    // first the chunks in even columns and even rows, then odd columns and
+    // even rows, and so on. Chunks in the same phase are a whole chunk apart,
+    // so they can all be updated at once, each by a board of its own working
+    // on the shared cells (see update_chunk). Serial and threaded ticks run
+    // the same phases with the same rngs, so they give the same board for the
+    // same seed.
+    pub(super) fn tick_chunks(&mut self) {
+        if self.edges != self.chunks.last_edges {
+            self.chunks.last_edges = self.edges;
+            self.chunks.wake_all();
+        }
+        self.chunks.wrap_columns = self.wraps_horizontally();
+        self.chunks.wrap_rows = self.wraps_vertically();
+
+        let chunk_count = self.chunks.awake.len();
+        let mut watched = vec![false; chunk_count];
+        for chunk in 0..chunk_count {
+            if !self.chunks.awake[chunk] {
+                continue
+            }
+            for neighbour in self.chunks.neighbours(chunk) {
+                watched[neighbour] = true;
+            }
+            // a chunk that slept through some ticks has update_toggles left
+            // over from whenever it last moved, which would make some cells
+            // wait an extra tick
+            if !self.chunks.was_awake[chunk] {
+                let (x_start, y_start, x_end, y_end) = self.chunk_bounds(chunk);
+                for y in y_start..y_end {
+                    for x in x_start..x_end {
+                        let index = self.get_index_from_coordinates(x, y);
+                        self.granules[index].update_toggle = !self.update_cycle;
+                    }
+                }
+            }
+        }
+
+        let mut snapshot = std::mem::take(&mut self.chunks.snapshot);
+        snapshot.clear();
+        for (chunk, is_watched) in watched.iter().enumerate() {
+            if *is_watched {
+                let (x_start, y_start, x_end, y_end) = self.chunk_bounds(chunk);
+                for y in y_start..y_end {
+                    let row_start = self.get_index_from_coordinates(x_start, y);
+                    snapshot.extend_from_slice(&self.granules[row_start..row_start + (x_end - x_start)]);
+                }
+            }
+        }
+
+        // every chunk gets its own rng, all seeded from this, so it doesn't
+        // matter which thread gets to a chunk first
+        let tick_seed: u64 = self.rng.gen();
+        let (columns, rows) = (self.chunks.columns, self.chunks.rows);
+        let seam_column = seam(columns, self.width, self.chunks.wrap_columns);
+        let seam_row = seam(rows, self.height, self.chunks.wrap_rows);
+        for phase in 0..4 {
+            let (phase_chunks, seam_chunks): (Vec<usize>, Vec<usize>) = (0..chunk_count)
+                .filter(|chunk| self.chunks.awake[*chunk])
+                .filter(|chunk| chunk % columns % 2 == phase % 2 && chunk / columns % 2 == phase / 2)
+                .partition(|chunk| Some(chunk % columns) != seam_column && Some(chunk / columns) != seam_row);
+            let jobs: Vec<(usize, SandBoard)> = phase_chunks.into_iter()
+                .map(|chunk| (chunk, self.chunk_board(chunk, tick_seed)))
+                .collect();
+            self.run_jobs(jobs, |(chunk, mut board)| board.update_chunk(chunk, columns));
+            for chunk in seam_chunks {
+                self.chunk_board(chunk, tick_seed).update_chunk(chunk, columns);
+            }
+        }
+
+        let awake_chunks: Vec<usize> = (0..chunk_count).filter(|chunk| self.chunks.awake[*chunk]).collect();
+        self.diffuse_heat(&awake_chunks);
+        let levelled = self.equalize_levels(&awake_chunks);
+
+        // Busy chunks usually show a change in their first few cells, so the
+        // full comparison only really runs for chunks that are settling down.
+        let mut next_awake = vec![false; chunk_count];
+        let mut snapshot_start = 0;
+        for chunk in 0..chunk_count {
+            self.chunks.has_changed[chunk] = false;
+            if !watched[chunk] {
+                continue
+            }
+            let mut has_changed = false;
+            let mut has_living = false;
+            let (x_start, y_start, x_end, y_end) = self.chunk_bounds(chunk);
+            let row_length = x_end - x_start;
+            for y in y_start..y_end {
+                let row_start = self.get_index_from_coordinates(x_start, y);
+                let before_row = &snapshot[snapshot_start..snapshot_start + row_length];
+                let after_row = &self.granules[row_start..row_start + row_length];
+                snapshot_start += row_length;
+                if has_changed {
+                    continue
+                }
+                for (before, after) in before_row.iter().zip(after_row) {
+                    if !is_same_cell(before, after) {
+                        has_changed = true;
+                        break
+                    }
+                    if after.element_type.is_living() {
+                        has_living = true;
+                    }
+                }
+            }
+            self.chunks.has_changed[chunk] = has_changed;
+            if has_changed {
+                for neighbour in self.chunks.neighbours(chunk) {
+                    next_awake[neighbour] = true;
+                }
+            }
+            else if has_living {
+                next_awake[chunk] = true;
+            }
+        }
+
+        self.chunks.snapshot = snapshot;
+        self.chunks.was_awake = std::mem::replace(&mut self.chunks.awake, next_awake);
+        // levelling can move water to a chunk nowhere near the awake ones
+        for index in levelled {
+            let (x, y) = self.get_coordinates_from_index(index);
+            self.wake_region(x, y, x, y);
+        }
+    }
+
+    // Runs `work` on every job, spread over the board's threads, and hands
+    // the results back in the same order as the jobs.
+    pub(super) fn run_jobs<J: Send, T: Send>(&self, jobs: Vec<J>, work: impl Fn(J) -> T + Sync) -> Vec<T> {
+        let threads = self.threads.min(jobs.len());
+        if threads <= 1 {
+            return jobs.into_iter().map(work).collect()
+        }
+
+        let jobs_per_thread = jobs.len().div_ceil(threads);
+        let work = &work;
+        let mut jobs = jobs.into_iter();
+        std::thread::scope(|scope| {
+            let mut workers = Vec::with_capacity(threads);
+            loop {
+                let group: Vec<J> = jobs.by_ref().take(jobs_per_thread).collect();
+                if group.is_empty() {
+                    break
+                }
+                workers.push(scope.spawn(move || group.into_iter().map(work).collect::<Vec<T>>()));
+            }
+            workers.into_iter()
+                .flat_map(|worker| worker.join().expect("chunk update thread panicked"))
+                .collect()
+        })
+    }
+
+    // A board that works on this board's cells, to update one chunk with.
+    // It has the chunk's own rng, and the board's size, edges and update
+    // cycle, so cells go just where they would have on this board.
+    fn chunk_board(&mut self, chunk: usize, tick_seed: u64) -> SandBoard {
+        let chunk_seed = tick_seed ^ (chunk as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        // starting from an empty board skips filling in cells that would just
+        // be thrown away, which adds up with a thousand chunks a tick
+        let mut board = SandBoard::with_seed(0, 0, chunk_seed);
+        board.granules = self.granules.view();
+        board.width = self.width;
+        board.height = self.height;
+        board.edges = self.edges;
+        board.update_cycle = self.update_cycle;
+        board.registry = self.registry.clone();
+        board
+    }
+
+    fn update_chunk(&mut self, chunk: usize, columns: usize) {
+        let x_start = (chunk % columns) * CHUNK_SIZE;
+        let y_start = (chunk / columns) * CHUNK_SIZE;
+        let x_end = (x_start + CHUNK_SIZE).min(self.width);
+        let y_end = (y_start + CHUNK_SIZE).min(self.height);
+        for y in y_start..y_end {
+            let reversed = y.is_multiple_of(2);
+            for k in 0..x_end - x_start {
+                let x = if reversed { x_end - 1 - k } else { x_start + k };
+                let index = self.get_index_from_coordinates(x, y);
+                if self.granules[index].element_type == ElementType::Empty {
+                    continue;
+                }
+                self.granule_tick_new(index);
+            }
+        }
+    }
+}
+
+// The one column (or row) of chunks along a wrapping edge that can reach the
+// same cells as another in its phase, which runs on its own after the rest.
+// Alternating columns keeps the chunks of a phase a whole chunk apart, except
+// across the edge: with an odd number of columns the last one is next to the
+// first, and with an even number the one before the last is less than a
+// chunk from the first when the last is narrower than a chunk.
+fn seam(count: usize, size: usize, wraps: bool) -> Option<usize> {
+    if !wraps || count < 3 {
+        return None
+    }
+    if !count.is_multiple_of(2) {
+        return Some(count - 1)
+    }
+    if !size.is_multiple_of(CHUNK_SIZE) {
+        return Some(count - 2)
+    }
+    None
+}
+
+// Whether a cell counts as changed. Only the bookkeeping flags are ignored:
+// update_toggle flips whenever a cell is visited, and liquids resting against
+// a wall keep turning around without going anywhere.
+fn is_same_cell(before: &Element, after: &Element) -> bool {
+    let mut before = *before;
+    before.update_toggle = after.update_toggle;
+    before.direction = after.direction;
+    before == *after
+}
+
+
+#[cfg(test)]
+mod chunks_test {
+    use super::*;
+    use super::super::edges::EdgeMode;
+    use super::super::elements::ElementType;
+
+    #[test]
+    fn settled_board_sleeps() {
+        let mut sand_board = SandBoard::with_seed(100, 70, 3);
+        sand_board.boring_sand();
+        sand_board.add_granules(80, 10, 3, ElementType::Wall);
+        for _ in 0..3 {
+            sand_board.tick();
+        }
+        assert_eq!(sand_board.awake_chunk_count(), 0);
+
+        // sand dropped in the top left only wakes the chunks it falls through
+        sand_board.add_granules(5, 5, 2, ElementType::Sand);
+        let granules = sand_board.get_granule_count();
+        sand_board.tick();
+        let awake: Vec<ChunkActivity> = sand_board.chunk_activity().into_iter().filter(|chunk| chunk.is_awake).collect();
+        assert!(awake.iter().all(|chunk| chunk.x < 2 * CHUNK_SIZE));
+        for _ in 0..100 {
+            sand_board.tick();
+        }
+        assert_eq!(sand_board.get_granule_count(), granules);
+        assert_eq!(sand_board.awake_chunk_count(), 0);
+    }
+
+    #[test]
+    fn threaded_tick_matches_serial() {
+        let mut serial = SandBoard::with_seed(130, 100, 77);
+        let mut threaded = SandBoard::with_seed(130, 100, 77);
+        threaded.set_threads(4);
+        for board in [&mut serial, &mut threaded] {
+            board.randomize_ocean();
+            board.add_granules(40, 20, 5, ElementType::Egg);
+            board.add_granules(100, 5, 2, ElementType::Seed);
+            board.add_granules(70, 60, 6, ElementType::Dirt);
+            for tick in 0..200 {
+                if tick == 120 {
+                    board.edges.bottom = EdgeMode::Void;
+                }
+                board.tick();
+            }
+        }
+        assert!(serial.granules == threaded.granules);
+    }
+
+    #[test]
+    fn dropping_the_floor_wakes_the_board() {
+        let mut sand_board = SandBoard::with_seed(40, 40, 3);
+        sand_board.boring_sand();
+        for _ in 0..3 {
+            sand_board.tick();
+        }
+        assert_eq!(sand_board.awake_chunk_count(), 0);
+
+        sand_board.edges.bottom = EdgeMode::Void;
+        for _ in 0..5 {
+            sand_board.tick();
+        }
+        assert_eq!(sand_board.get_granule_count(), 0);
+    }
+}
@@ -0,0 +1,321 @@
+// Board edges. Each of the four edges is set on its own (SandBoard::set_edges):
+//
+//   solid     holds everything in, like a wall. The default for every edge.
+//   void      whatever goes off it is gone, so sand pours out of a board
+//             with a void bottom
+//   wrap      carries things round to the opposite edge, so a board wrapping
+//             left and right is a loop. Opposite edges wrap together, and
+//             set_edges turns down a wrap on only one of them.
+//   source    keeps spawning its element along the edge wherever there's
+//             room, and is solid otherwise. A water source along the top
+//             over a void bottom makes a waterfall.
+//
+// Everything that looks past the edge of the board goes through
+// resolve_coordinates, so neighbours wrap and void edges look empty.
+
+use std::fmt;
+
+use rand::Rng;
+
+use super::SandBoard;
+use super::elements::ElementType;
+use super::element_registry::ElementRegistry;
+
+// the chance each tick that a source edge spawns into each empty cell along it
+const SOURCE_CHANCE: f64 = 0.25;
+
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum EdgeMode {
+    Solid,
+    Void,
+    Wrap,
+    Source(ElementType),
+}
+
+impl EdgeMode {
+    // `solid`, `void`, `wrap` or `source:<element>`
+    pub fn from_name(name: &str, registry: &ElementRegistry) -> Option<EdgeMode> {
+        match name {
+            "solid" => Some(EdgeMode::Solid),
+            "void" => Some(EdgeMode::Void),
+            "wrap" => Some(EdgeMode::Wrap),
+            _ => {
+                let element_name = name.strip_prefix("source:")?;
+                registry.find(element_name).map(|definition| EdgeMode::Source(definition.element_type))
+            }
+        }
+    }
+
+    // (mode, element id) for save files
+    pub fn id(&self) -> (u8, u8) {
+        match self {
+            EdgeMode::Solid => (0, 0),
+            EdgeMode::Void => (1, 0),
+            EdgeMode::Wrap => (2, 0),
+            EdgeMode::Source(element_type) => (3, element_type.id()),
+        }
+    }
+
+    pub fn from_id(mode: u8, element_id: u8) -> Option<EdgeMode> {
+        match mode {
+            0 => Some(EdgeMode::Solid),
+            1 => Some(EdgeMode::Void),
+            2 => Some(EdgeMode::Wrap),
+            3 => ElementType::from_id(element_id).map(EdgeMode::Source),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Edges {
+    pub top: EdgeMode,
+    pub bottom: EdgeMode,
+    pub left: EdgeMode,
+    pub right: EdgeMode,
+}
+
+impl Edges {
+    // Whether every wrap edge has a wrap edge opposite it.
+    pub fn check(&self) -> Result<(), UnpairedWrap> {
+        for (side, edge, opposite, opposite_edge) in [
+            ("top", self.top, "bottom", self.bottom),
+            ("bottom", self.bottom, "top", self.top),
+            ("left", self.left, "right", self.right),
+            ("right", self.right, "left", self.left),
+        ] {
+            if edge == EdgeMode::Wrap && opposite_edge != EdgeMode::Wrap {
+                return Err(UnpairedWrap { side, opposite })
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Edges {
+    fn default() -> Self {
+        Self {
+            top: EdgeMode::Solid,
+            bottom: EdgeMode::Solid,
+            left: EdgeMode::Solid,
+            right: EdgeMode::Solid,
+        }
+    }
+}
+
+
+// A wrap edge whose opposite edge doesn't wrap.
+#[derive(PartialEq, Debug)]
+pub struct UnpairedWrap {
+    pub side: &'static str,
+    pub opposite: &'static str,
+}
+
+impl fmt::Display for UnpairedWrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the {} edge wraps but the {} edge doesn't, wrapping goes on both or neither", self.side, self.opposite)
+    }
+}
+
+impl std::error::Error for UnpairedWrap {}
+
+
+impl SandBoard {
+    pub fn edges(&self) -> Edges {
+        self.edges
+    }
+
+    // Sets all four edges, unless a wrap edge is opposite one that doesn't
+    // wrap, which leaves the edges as they were.
+    pub fn set_edges(&mut self, edges: Edges) -> Result<(), UnpairedWrap> {
+        edges.check()?;
+        self.edges = edges;
+        Ok(())
+    }
+
+    pub fn wraps_horizontally(&self) -> bool {
+        self.edges.left == EdgeMode::Wrap && self.edges.right == EdgeMode::Wrap
+    }
+
+    pub fn wraps_vertically(&self) -> bool {
+        self.edges.top == EdgeMode::Wrap && self.edges.bottom == EdgeMode::Wrap
+    }
+
+    // Where the cell at x, y is on the board, once it has wrapped round any
+    // wrapping edges. Cells off the board past any other edge come back as
+    // the mode of the edge they're past.
+    pub(super) fn resolve_coordinates(&self, x: i64, y: i64) -> Result<(usize, usize), EdgeMode> {
+        if self.is_in_coordinate_bounds_signed(x, y) {
+            return Ok((x as usize, y as usize))
+        }
+        let (width, height) = (self.width as i64, self.height as i64);
+        let mut x = x;
+        if x < 0 || x >= width {
+            if !self.wraps_horizontally() {
+                return Err(if x < 0 { self.edges.left } else { self.edges.right })
+            }
+            x = x.rem_euclid(width);
+        }
+        let mut y = y;
+        if y < 0 || y >= height {
+            if !self.wraps_vertically() {
+                return Err(if y < 0 { self.edges.top } else { self.edges.bottom })
+            }
+            y = y.rem_euclid(height);
+        }
+        Ok((x as usize, y as usize))
+    }
+
+    // Empties the granule at `index` if moving it by x_relative, y_relative
+    // would take it off a void edge. Returns whether it's gone.
+    pub(super) fn is_leaving_board(&mut self, index: usize, x_relative: i64, y_relative: i64) -> bool {
+        let (x, y) = self.get_coordinates_from_index(index);
+        if self.resolve_coordinates(x as i64 + x_relative, y as i64 + y_relative) != Err(EdgeMode::Void) {
+            return false
+        }
+        self.granules[index] = self.new_element(ElementType::Empty);
+        self.granules[index].update_toggle = self.update_cycle;
+        true
+    }
+
+    // Spawns the source edges' elements into the empty cells along them.
+    pub(super) fn feed_sources(&mut self) {
+        if self.width == 0 || self.height == 0 {
+            return
+        }
+        let (width, height) = (self.width, self.height);
+        let Edges { top, bottom, left, right } = self.edges;
+        if let EdgeMode::Source(element_type) = top {
+            self.feed_source(element_type, (0..width).map(|x| (x, 0)));
+        }
+        if let EdgeMode::Source(element_type) = bottom {
+            self.feed_source(element_type, (0..width).map(|x| (x, height - 1)));
+        }
+        if let EdgeMode::Source(element_type) = left {
+            self.feed_source(element_type, (0..height).map(|y| (0, y)));
+        }
+        if let EdgeMode::Source(element_type) = right {
+            self.feed_source(element_type, (0..height).map(|y| (width - 1, y)));
+        }
+    }
+
+    fn feed_source(&mut self, element_type: ElementType, cells: impl Iterator<Item = (usize, usize)>) {
+        for (x, y) in cells {
+            let index = self.get_index_from_coordinates(x, y);
+            if self.granules[index].element_type != ElementType::Empty || !self.rng.gen_bool(SOURCE_CHANCE) {
+                continue
+            }
+            self.granules[index] = self.new_element(element_type);
+            self.wake_region(x, y, x, y);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod edges_test {
+    use super::*;
+    use super::super::weather::RAINDROP_MOISTURE;
+
+    fn count(sand_board: &SandBoard, element_type: ElementType) -> usize {
+        sand_board.granules.iter().filter(|granule| granule.element_type == element_type).count()
+    }
+
+    #[test]
+    fn wrapping_edges_carry_things_round() {
+        let wrap = Edges { top: EdgeMode::Wrap, bottom: EdgeMode::Wrap, left: EdgeMode::Wrap, right: EdgeMode::Wrap };
+        // less than a chunk across, and an odd number of chunks down
+        for (width, height) in [(112, 100), (20, 70)] {
+            let mut serial = SandBoard::with_seed(width, height, 12);
+            let mut threaded = SandBoard::with_seed(width, height, 12);
+            threaded.set_threads(4);
+            let mut sand = 0;
+            let shelf = 60;
+            for board in [&mut serial, &mut threaded] {
+                board.set_edges(wrap).unwrap();
+                // a shelf with water poured at its right end, and sand
+                // falling through the floor
+                for x in 0..width {
+                    board.add_granule(x, shelf, ElementType::Wall);
+                }
+                for y in shelf - 10..shelf {
+                    for x in width - 12..width {
+                        board.add_granule(x, y, ElementType::Water);
+                    }
+                }
+                sand = board.add_granules(width / 2, shelf + 5, 2, ElementType::Sand);
+                for _ in 0..200 {
+                    board.tick();
+                }
+            }
+            assert!(serial.granules == threaded.granules);
+            let water_on_the_left = (0..5).any(|x| serial.get_surrounding(x, shelf - 1)[1][1].element_type == ElementType::Water);
+            assert!(water_on_the_left);
+            // some of the water may have evaporated into clouds
+            let water = serial.granules.iter().map(|granule| match granule.element_type {
+                ElementType::Water => RAINDROP_MOISTURE as usize,
+                ElementType::Cloud => granule.moisture as usize,
+                _ => 0,
+            }).sum::<usize>();
+            assert_eq!(water, 120 * RAINDROP_MOISTURE as usize);
+            assert_eq!(count(&serial, ElementType::Sand), sand);
+        }
+    }
+
+    #[test]
+    fn void_edges_drain_and_sources_refill() {
+        let mut sand_board = SandBoard::with_seed(64, 48, 13);
+        sand_board.edges.left = EdgeMode::Void;
+        for y in 30..48 {
+            for x in 0..32 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        for _ in 0..300 {
+            sand_board.tick();
+        }
+        assert_eq!(count(&sand_board, ElementType::Water), 0);
+
+        // sand rain through a board with a source on top and no floor
+        sand_board.edges.top = EdgeMode::Source(ElementType::Sand);
+        sand_board.edges.bottom = EdgeMode::Void;
+        let mut counts = Vec::new();
+        for _ in 0..300 {
+            sand_board.tick();
+            counts.push(count(&sand_board, ElementType::Sand));
+        }
+        assert!(counts[299] > 100);
+        // what comes in at the top goes out at the bottom
+        assert!(counts[299].abs_diff(counts[199]) < counts[199] / 5, "{} then {}", counts[199], counts[299]);
+    }
+
+    #[test]
+    fn surroundings_follow_the_edges() {
+        let mut sand_board = SandBoard::with_seed(96, 10, 1);
+        let mut edges = Edges { left: EdgeMode::Wrap, right: EdgeMode::Wrap, top: EdgeMode::Void, ..Edges::default() };
+        sand_board.set_edges(edges).unwrap();
+        sand_board.add_granule(95, 0, ElementType::Sand);
+        let surrounding = sand_board.get_surrounding(0, 0);
+        assert_eq!(surrounding[0][1].element_type, ElementType::Empty);
+        assert_eq!(surrounding[1][0].element_type, ElementType::Sand);
+        assert_eq!(surrounding[2][1].element_type, ElementType::Empty);
+
+        // however short the board is
+        sand_board.add_granule(0, 9, ElementType::Wall);
+        edges.top = EdgeMode::Wrap;
+        edges.bottom = EdgeMode::Wrap;
+        sand_board.set_edges(edges).unwrap();
+        assert_eq!(sand_board.get_surrounding(0, 0)[0][1].element_type, ElementType::Wall);
+    }
+
+    #[test]
+    fn wrapping_takes_both_sides() {
+        let mut sand_board = SandBoard::with_seed(40, 40, 1);
+        let edges = Edges { left: EdgeMode::Wrap, bottom: EdgeMode::Void, ..Edges::default() };
+        assert_eq!(sand_board.set_edges(edges), Err(UnpairedWrap { side: "left", opposite: "right" }));
+        assert_eq!(sand_board.edges(), Edges::default());
+        assert!(!sand_board.wraps_horizontally());
+    }
+}
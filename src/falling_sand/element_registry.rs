@@ -0,0 +1,664 @@
+// Element definitions: what each element looks like, how heavy it is and how
+// it moves. The built in elements are defined here, and a definitions file can
+// retune them or add new elements without recompiling.
+//
+// Definitions file format, one section per element:
+//
+//   # lines starting with # are comments
+//   [oil]
+//   color = #3A2A10        (#RRGGBB or #RRGGBBAA)
+//   behaviour = liquid     (powder, liquid, static or gas)
+//   state = liquid         (solid, granules, liquid or gas; defaults from behaviour)
+//   density = 80           (0-255, heavier sinks through lighter, but only
+//                          liquids and gases get pushed out of the way)
+//   moisture = 0           (starting moisture, 0-255)
+//   growth = 0             (starting growth, 0-255)
+//   temperature = 20       (starting temperature in degrees celsius)
+//   conductivity = 60      (0-255, how quickly heat passes through it)
+//   heat_capacity = 2      (1-255, how much heat it takes to warm it up)
+//   flammability = 0       (0-255, the chance out of 255 each tick that it
+//                          catches from fire next to it, 0 never burns)
+//   transition = ice below 0 touching ice
+//                          (turns into another element once it's above or
+//                          below a temperature in degrees, or touching
+//                          another element, or all of those at once. Can be
+//                          given more than once, and replaces the element's
+//                          built in transitions. See transitions.rs)
+//
+// A section named after a built in element (sand, water, dirt, ...) changes
+// just the keys it sets. Any other name adds a new element, which needs at
+// least a color and a behaviour. A transition can only turn an element into
+// one that's built in or defined further up the file (or into itself). Living
+// elements (seeds, grass, kelp, moss, eggs, minnows, tadpoles, frogs,
+// isopods, snails, springtails), fire, smoke, clouds, emitters and drains
+// keep their own update code, so setting their behaviour is an error.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use rand::Rng;
+
+use super::*;
+use super::weather::RAINDROP_MOISTURE;
+use super::elements::{degrees, Element, ElementType, State, AMBIENT_TEMPERATURE, CUSTOM_ELEMENT_ID_START, TEMPERATURE_SCALE};
+
+// (line number, key, value) of one line in a definitions file section
+type SectionKey = (usize, String, String);
+
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Behaviour {
+    Powder,
+    Liquid,
+    Static,
+    Gas,
+}
+
+impl Behaviour {
+    fn from_name(name: &str) -> Option<Behaviour> {
+        match name {
+            "powder" => Some(Behaviour::Powder),
+            "liquid" => Some(Behaviour::Liquid),
+            "static" => Some(Behaviour::Static),
+            "gas" => Some(Behaviour::Gas),
+            _ => None,
+        }
+    }
+
+    fn default_state(&self) -> State {
+        match self {
+            Behaviour::Powder => State::Granules,
+            Behaviour::Liquid => State::Liquid,
+            Behaviour::Static => State::Solid,
+            Behaviour::Gas => State::Gas,
+        }
+    }
+
+    fn default_density(&self) -> u8 {
+        match self {
+            Behaviour::Powder => 150,
+            Behaviour::Liquid => 100,
+            Behaviour::Static => 255,
+            Behaviour::Gas => 1,
+        }
+    }
+}
+
+fn state_from_name(name: &str) -> Option<State> {
+    match name {
+        "solid" => Some(State::Solid),
+        "granules" => Some(State::Granules),
+        "liquid" => Some(State::Liquid),
+        "gas" => Some(State::Gas),
+        _ => None,
+    }
+}
+
+
+// One way an element turns into another. Every condition that's set has to
+// hold, temperatures are in tenths of a degree.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Transition {
+    pub into: ElementType,
+    pub above: Option<i16>,
+    pub below: Option<i16>,
+    pub touching: Option<ElementType>,
+}
+
+impl Transition {
+    fn new(into: ElementType) -> Self {
+        Self {
+            into,
+            above: None,
+            below: None,
+            touching: None,
+        }
+    }
+
+    fn above(mut self, temperature: i16) -> Self {
+        self.above = Some(temperature);
+        self
+    }
+
+    fn below(mut self, temperature: i16) -> Self {
+        self.below = Some(temperature);
+        self
+    }
+
+    fn touching(mut self, element_type: ElementType) -> Self {
+        self.touching = Some(element_type);
+        self
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct ElementDefinition {
+    pub name: String,
+    pub element_type: ElementType,
+    pub color: [u8; 4],
+    pub state: State,
+    pub density: u8,
+    pub moisture: u8,
+    pub growth: u8,
+    pub behaviour: Behaviour,
+    pub temperature: i16,
+    pub conductivity: u8,
+    pub heat_capacity: u8,
+    pub flammability: u8,
+    // checked in order, the first that applies is the one that happens
+    pub transitions: Vec<Transition>,
+}
+
+impl ElementDefinition {
+    fn builtin(element_type: ElementType, name: &str, color: [u8; 4], behaviour: Behaviour, state: State, density: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            element_type,
+            color,
+            state,
+            density,
+            moisture: 0,
+            growth: 0,
+            behaviour,
+            temperature: AMBIENT_TEMPERATURE,
+            conductivity: DEFAULT_CONDUCTIVITY,
+            heat_capacity: DEFAULT_HEAT_CAPACITY,
+            flammability: 0,
+            transitions: Vec::new(),
+        }
+    }
+
+    fn with_heat(mut self, conductivity: u8, heat_capacity: u8) -> Self {
+        self.conductivity = conductivity;
+        self.heat_capacity = heat_capacity;
+        self
+    }
+
+    fn with_flammability(mut self, flammability: u8) -> Self {
+        self.flammability = flammability;
+        self
+    }
+
+    fn with_transition(mut self, transition: Transition) -> Self {
+        self.transitions.push(transition);
+        self
+    }
+}
+
+const DEFAULT_CONDUCTIVITY: u8 = 60;
+const DEFAULT_HEAT_CAPACITY: u8 = 2;
+
+
+#[derive(Debug)]
+pub enum DefinitionError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefinitionError::Io(error) => write!(f, "i/o error: {}", error),
+            DefinitionError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for DefinitionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DefinitionError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DefinitionError {
+    fn from(error: io::Error) -> Self {
+        DefinitionError::Io(error)
+    }
+}
+
+
+pub struct ElementRegistry {
+    // indexed by ElementType::id()
+    definitions: Vec<Option<ElementDefinition>>,
+}
+
+impl Default for ElementRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            definitions: vec![None; 256],
+        };
+        for definition in builtin_definitions() {
+            registry.insert(definition);
+        }
+        registry
+    }
+}
+
+impl ElementRegistry {
+    // The built in definitions, built once and shared by every board that
+    // doesn't load its own.
+    pub fn shared_default() -> Arc<ElementRegistry> {
+        static SHARED_DEFAULT: OnceLock<Arc<ElementRegistry>> = OnceLock::new();
+        SHARED_DEFAULT.get_or_init(|| Arc::new(ElementRegistry::default())).clone()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, DefinitionError> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    // Built in definitions with the contents of a definitions file on top.
+    pub fn parse(text: &str) -> Result<Self, DefinitionError> {
+        let mut registry = Self::default();
+        let mut section: Option<(usize, String, Vec<SectionKey>)> = None;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(parse_error(line_number, "section header is missing its closing ]"))
+                }
+                if let Some((start, name, keys)) = section.take() {
+                    registry.apply_section(start, &name, &keys)?;
+                }
+                let name = line[1..line.len() - 1].trim().to_lowercase();
+                if name.is_empty() {
+                    return Err(parse_error(line_number, "empty element name"))
+                }
+                section = Some((line_number, name, Vec::new()));
+                continue
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(parse_error(line_number, "expected `key = value`"))
+            };
+            match section.as_mut() {
+                Some((_, _, keys)) => keys.push((line_number, key.trim().to_lowercase(), value.trim().to_string())),
+                None => return Err(parse_error(line_number, "key outside of an [element] section")),
+            }
+        }
+        if let Some((start, name, keys)) = section.take() {
+            registry.apply_section(start, &name, &keys)?;
+        }
+        Ok(registry)
+    }
+
+    fn apply_section(&mut self, start_line: usize, name: &str, keys: &[SectionKey]) -> Result<(), DefinitionError> {
+        let mut definition = match self.find(name) {
+            Some(existing) => existing.clone(),
+            None => {
+                let behaviour_value = keys.iter().find(|(_, key, _)| key == "behaviour");
+                let Some((line, _, value)) = behaviour_value else {
+                    return Err(parse_error(start_line, &format!("new element '{}' needs a behaviour", name)))
+                };
+                let Some(behaviour) = Behaviour::from_name(value) else {
+                    return Err(parse_error(*line, &format!("unknown behaviour '{}'", value)))
+                };
+                if !keys.iter().any(|(_, key, _)| key == "color") {
+                    return Err(parse_error(start_line, &format!("new element '{}' needs a color", name)))
+                }
+                let Some(element_type) = self.next_custom_type() else {
+                    return Err(parse_error(start_line, "too many custom elements"))
+                };
+                ElementDefinition {
+                    name: name.to_string(),
+                    element_type,
+                    color: [0, 0, 0, 0xFF],
+                    state: behaviour.default_state(),
+                    density: behaviour.default_density(),
+                    moisture: 0,
+                    growth: 0,
+                    behaviour,
+                    temperature: AMBIENT_TEMPERATURE,
+                    conductivity: DEFAULT_CONDUCTIVITY,
+                    heat_capacity: DEFAULT_HEAT_CAPACITY,
+                    flammability: 0,
+                    transitions: Vec::new(),
+                }
+            }
+        };
+
+        let sets_state = keys.iter().any(|(_, key, _)| key == "state");
+        if keys.iter().any(|(_, key, _)| key == "transition") {
+            definition.transitions.clear();
+        }
+        for (line, key, value) in keys {
+            let line = *line;
+            match key.as_str() {
+                "color" => definition.color = parse_color(value).ok_or_else(|| parse_error(line, &format!("bad color '{}'", value)))?,
+                "behaviour" => {
+                    if definition.element_type.has_own_update() {
+                        return Err(parse_error(line, &format!("'{}' has its own update code, its behaviour can't be changed", name)))
+                    }
+                    definition.behaviour = Behaviour::from_name(value)
+                        .ok_or_else(|| parse_error(line, &format!("unknown behaviour '{}'", value)))?;
+                    if !sets_state {
+                        definition.state = definition.behaviour.default_state();
+                    }
+                },
+                "state" => definition.state = state_from_name(value).ok_or_else(|| parse_error(line, &format!("unknown state '{}'", value)))?,
+                "density" => definition.density = parse_byte(line, key, value)?,
+                "moisture" => definition.moisture = parse_byte(line, key, value)?,
+                "growth" => definition.growth = parse_byte(line, key, value)?,
+                "temperature" => {
+                    definition.temperature = value.parse::<i16>().ok()
+                        .and_then(|celsius| celsius.checked_mul(TEMPERATURE_SCALE))
+                        .ok_or_else(|| parse_error(line, &format!("temperature must be a whole number of degrees between -3276 and 3276, got '{}'", value)))?;
+                },
+                "conductivity" => definition.conductivity = parse_byte(line, key, value)?,
+                "heat_capacity" => {
+                    definition.heat_capacity = parse_byte(line, key, value)?;
+                    if definition.heat_capacity == 0 {
+                        return Err(parse_error(line, "heat_capacity must be at least 1"))
+                    }
+                },
+                "flammability" => definition.flammability = parse_byte(line, key, value)?,
+                "transition" => {
+                    let transition = self.parse_transition(line, value, &definition)?;
+                    definition.transitions.push(transition);
+                },
+                _ => return Err(parse_error(line, &format!("unknown key '{}'", key))),
+            }
+        }
+        self.insert(definition);
+        Ok(())
+    }
+
+    // `<element> [above <degrees>] [below <degrees>] [touching <element>]`
+    fn parse_transition(&self, line: usize, value: &str, definition: &ElementDefinition) -> Result<Transition, DefinitionError> {
+        let element_named = |name: &str| {
+            if name == definition.name {
+                return Ok(definition.element_type)
+            }
+            self.find(name)
+                .map(|other| other.element_type)
+                .ok_or_else(|| parse_error(line, &format!("unknown element '{}' in transition", name)))
+        };
+        let degrees_from = |text: Option<&str>| {
+            text.and_then(|text| text.parse::<i16>().ok())
+                .and_then(|celsius| celsius.checked_mul(TEMPERATURE_SCALE))
+                .ok_or_else(|| parse_error(line, &format!("transition temperatures must be whole numbers of degrees between -3276 and 3276, in '{}'", value)))
+        };
+
+        let mut words = value.split_whitespace();
+        let Some(into) = words.next() else {
+            return Err(parse_error(line, "transition needs an element to turn into"))
+        };
+        let mut transition = Transition::new(element_named(&into.to_lowercase())?);
+        while let Some(condition) = words.next() {
+            match condition {
+                "above" => transition.above = Some(degrees_from(words.next())?),
+                "below" => transition.below = Some(degrees_from(words.next())?),
+                "touching" => {
+                    let Some(name) = words.next() else {
+                        return Err(parse_error(line, "touching needs an element"))
+                    };
+                    transition.touching = Some(element_named(&name.to_lowercase())?);
+                },
+                _ => return Err(parse_error(line, &format!("unknown transition condition '{}', expected above, below or touching", condition))),
+            }
+        }
+        if transition.above.is_none() && transition.below.is_none() && transition.touching.is_none() {
+            return Err(parse_error(line, "transition needs an above, below or touching condition"))
+        }
+        Ok(transition)
+    }
+
+    fn insert(&mut self, definition: ElementDefinition) {
+        let id = definition.element_type.id() as usize;
+        self.definitions[id] = Some(definition);
+    }
+
+    fn next_custom_type(&self) -> Option<ElementType> {
+        (CUSTOM_ELEMENT_ID_START..=u8::MAX)
+            .find(|id| self.definitions[*id as usize].is_none())
+            .and_then(ElementType::from_id)
+    }
+
+    pub fn get(&self, element_type: ElementType) -> Option<&ElementDefinition> {
+        self.definitions[element_type.id() as usize].as_ref()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ElementDefinition> {
+        self.definitions().find(|definition| definition.name == name)
+    }
+
+    pub fn definitions(&self) -> impl Iterator<Item = &ElementDefinition> {
+        self.definitions.iter().flatten()
+    }
+
+    pub fn custom_elements(&self) -> Vec<ElementType> {
+        self.definitions()
+            .map(|definition| definition.element_type)
+            .filter(|element_type| matches!(element_type, ElementType::Custom(_)))
+            .collect()
+    }
+
+    pub fn color(&self, element_type: ElementType) -> [u8; 4] {
+        match self.get(element_type) {
+            Some(definition) => definition.color,
+            None => COLORS_MARIO_BACKGROUND,
+        }
+    }
+
+    pub fn behaviour(&self, element_type: ElementType) -> Option<Behaviour> {
+        self.get(element_type).map(|definition| definition.behaviour)
+    }
+
+    // undefined elements weigh nothing
+    pub fn density(&self, element_type: ElementType) -> u8 {
+        self.get(element_type).map_or(0, |definition| definition.density)
+    }
+
+    // (conductivity, heat_capacity), undefined elements don't conduct
+    pub fn heat_properties(&self, element_type: ElementType) -> (u8, u8) {
+        match self.get(element_type) {
+            Some(definition) => (definition.conductivity, definition.heat_capacity),
+            None => (0, 1),
+        }
+    }
+
+    // How readily an element catches fire, 0 for anything that doesn't burn
+    pub fn flammability(&self, element_type: ElementType) -> u8 {
+        self.get(element_type).map_or(0, |definition| definition.flammability)
+    }
+
+    // undefined elements never change
+    pub fn transitions(&self, element_type: ElementType) -> &[Transition] {
+        self.get(element_type).map_or(&[], |definition| &definition.transitions)
+    }
+
+    // Creates a fresh element. Elements without a definition come out as
+    // empty space.
+    pub fn create<R: Rng + ?Sized>(&self, element_type: ElementType, rng: &mut R) -> Element {
+        match self.get(element_type) {
+            Some(definition) => Element::new(definition, rng),
+            None => Element::blank(ElementType::Empty),
+        }
+    }
+
+    // Every element you could paint, with the color it's drawn in.
+    pub fn palette(&self) -> Vec<(ElementType, [u8; 4])> {
+        self.definitions()
+            .filter(|definition| definition.element_type != ElementType::ScreenEdge)
+            .map(|definition| (definition.element_type, definition.color))
+            .collect()
+    }
+}
+
+fn builtin_definitions() -> Vec<ElementDefinition> {
+    let mut water = ElementDefinition::builtin(ElementType::Water, "water", COLORS_BLUE, Behaviour::Liquid, State::Liquid, 100)
+        .with_transition(Transition::new(ElementType::Ice).below(degrees(0)))
+        // ice sets water next to it freezing a little before it would on its own
+        .with_transition(Transition::new(ElementType::Ice).below(degrees(2)).touching(ElementType::Ice))
+        .with_transition(Transition::new(ElementType::Steam).above(degrees(100)));
+    water.moisture = 255;
+    let mut ice = ElementDefinition::builtin(ElementType::Ice, "ice", COLORS_ICE_BLUE, Behaviour::Static, State::Solid, 90)
+        .with_transition(Transition::new(ElementType::Water).above(degrees(0)));
+    ice.temperature = degrees(-10);
+    // steam turns back into water a bit cooler than water boils, so it gets
+    // some way up before it condenses
+    let mut steam = ElementDefinition::builtin(ElementType::Steam, "steam", COLORS_STEAM_GREY, Behaviour::Gas, State::Gas, 1)
+        .with_transition(Transition::new(ElementType::Water).below(degrees(90)));
+    steam.temperature = degrees(110);
+    let mut snow = ElementDefinition::builtin(ElementType::Snow, "snow", COLORS_SNOW_WHITE, Behaviour::Powder, State::Granules, 40)
+        .with_transition(Transition::new(ElementType::Water).above(degrees(0)));
+    snow.temperature = degrees(-5);
+    // an emitter's growth is the id of the element it puts out
+    let mut emitter = ElementDefinition::builtin(ElementType::Emitter, "emitter", COLORS_TEAL, Behaviour::Static, State::Solid, 255);
+    emitter.growth = ElementType::Water.id();
+    // fire's growth is how many ticks a freshly painted flame burns for
+    let mut fire = ElementDefinition::builtin(ElementType::Fire, "fire", COLORS_ORANGE, Behaviour::Static, State::Gas, 0);
+    fire.growth = 30;
+    fire.temperature = degrees(600);
+    let mut smoke = ElementDefinition::builtin(ElementType::Smoke, "smoke", COLORS_DARK_GREY, Behaviour::Gas, State::Gas, 1);
+    smoke.temperature = degrees(150);
+    // a painted cloud holds two raindrops, so it rains once it meets another
+    let mut cloud = ElementDefinition::builtin(ElementType::Cloud, "cloud", COLORS_WHITE, Behaviour::Gas, State::Gas, 2);
+    cloud.moisture = 2 * RAINDROP_MOISTURE;
+    let mut moss = ElementDefinition::builtin(ElementType::Moss, "moss", COLORS_MOSS_GREEN, Behaviour::Static, State::Solid, 255);
+    moss.moisture = 160;
+    let mut tadpole = ElementDefinition::builtin(ElementType::Tadpole, "tadpole", COLORS_DARK_BROWN, Behaviour::Static, State::Granules, 100);
+    tadpole.moisture = u8::MAX;
+    let mut frog = ElementDefinition::builtin(ElementType::Frog, "frog", COLORS_FROG_GREEN, Behaviour::Static, State::Granules, 110);
+    frog.moisture = u8::MAX;
+    let mut snail = ElementDefinition::builtin(ElementType::Snail, "snail", COLORS_TAN, Behaviour::Static, State::Granules, 140);
+    snail.moisture = u8::MAX;
+    let mut springtail = ElementDefinition::builtin(ElementType::SpringTail, "springtail", COLORS_LILAC, Behaviour::Static, State::Granules, 60);
+    springtail.moisture = u8::MAX;
+
+    // air barely conducts, so heat mostly travels with whatever is moving
+    vec![
+        ElementDefinition::builtin(ElementType::Empty, "empty", COLORS_MARIO_BACKGROUND, Behaviour::Gas, State::Gas, 0).with_heat(16, 1),
+        ElementDefinition::builtin(ElementType::Sand, "sand", COLORS_YELLOW, Behaviour::Powder, State::Granules, 160).with_heat(70, 2),
+        water.with_heat(120, 4),
+        ElementDefinition::builtin(ElementType::Dirt, "dirt", COLORS_BROWN, Behaviour::Powder, State::Granules, 150).with_heat(50, 2),
+        ElementDefinition::builtin(ElementType::Seed, "seed", COLORS_LIGHT_GREEN, Behaviour::Powder, State::Granules, 120).with_heat(30, 2).with_flammability(50),
+        ElementDefinition::builtin(ElementType::Grass, "grass", COLORS_PALE_YELLOW, Behaviour::Static, State::Solid, 255).with_heat(30, 2).with_flammability(80),
+        ElementDefinition::builtin(ElementType::Kelp, "kelp", COLORS_DARK_GREEN, Behaviour::Static, State::Solid, 255).with_heat(60, 3).with_flammability(40),
+        ElementDefinition::builtin(ElementType::Wall, "wall", COLORS_GREY, Behaviour::Static, State::Solid, 255).with_heat(200, 3),
+        ElementDefinition::builtin(ElementType::Egg, "egg", COLORS_DARK_ORANGE, Behaviour::Powder, State::Granules, 110).with_heat(40, 3).with_flammability(25),
+        ElementDefinition::builtin(ElementType::Minnow, "minnow", COLORS_DARK_RED, Behaviour::Static, State::Granules, 100).with_heat(60, 4),
+        ElementDefinition::builtin(ElementType::ScreenEdge, "screen_edge", COLORS_BLACK, Behaviour::Static, State::Solid, 255).with_heat(0, 1),
+        fire.with_heat(40, 1),
+        smoke.with_heat(16, 1),
+        ElementDefinition::builtin(ElementType::Ash, "ash", COLORS_WARM_GREY, Behaviour::Powder, State::Granules, 90).with_heat(20, 1),
+        cloud.with_heat(16, 1),
+        moss.with_heat(30, 2).with_flammability(60),
+        tadpole.with_heat(60, 4),
+        frog.with_heat(60, 4),
+        ElementDefinition::builtin(ElementType::Isopod, "isopod", COLORS_SLATE, Behaviour::Static, State::Granules, 130).with_heat(40, 3),
+        ElementDefinition::builtin(ElementType::Detritus, "detritus", COLORS_OLIVE_BROWN, Behaviour::Powder, State::Granules, 110).with_heat(30, 2).with_flammability(70),
+        snail.with_heat(50, 3),
+        springtail.with_heat(40, 2),
+        ice.with_heat(200, 2),
+        steam.with_heat(16, 4),
+        snow.with_heat(20, 2),
+        emitter.with_heat(200, 3),
+        ElementDefinition::builtin(ElementType::Drain, "drain", COLORS_CHARCOAL, Behaviour::Static, State::Solid, 255).with_heat(200, 3),
+    ]
+}
+
+fn parse_error(line: usize, message: &str) -> DefinitionError {
+    DefinitionError::Parse { line, message: message.to_string() }
+}
+
+fn parse_byte(line: usize, key: &str, value: &str) -> Result<u8, DefinitionError> {
+    value.parse::<u8>().map_err(|_| parse_error(line, &format!("{} must be a number from 0 to 255, got '{}'", key, value)))
+}
+
+fn parse_color(value: &str) -> Option<[u8; 4]> {
+    let hex = value.strip_prefix('#')?;
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return None
+    }
+    let mut color = [0xFF; 4];
+    for i in 0..hex.len() / 2 {
+        color[i] = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+
+#[cfg(test)]
+mod element_registry_test {
+    use super::*;
+
+    #[test]
+    fn builtins_and_custom_elements() {
+        let registry = ElementRegistry::parse("
+            # heavier sand, and an oil that floats on water
+            [sand]
+            density = 200
+
+            [oil]
+            color = #3A2A10
+            behaviour = liquid
+            density = 80
+        ").unwrap();
+
+        let sand = registry.get(ElementType::Sand).unwrap();
+        assert_eq!(sand.density, 200);
+        assert_eq!(sand.color, COLORS_YELLOW);
+
+        let oil = registry.find("oil").unwrap();
+        assert_eq!(oil.element_type, ElementType::Custom(0));
+        assert_eq!(oil.color, [0x3A, 0x2A, 0x10, 0xFF]);
+        assert_eq!(oil.state, State::Liquid);
+        assert_eq!(oil.density, 80);
+        assert_eq!(registry.custom_elements(), vec![ElementType::Custom(0)]);
+    }
+
+    #[test]
+    fn transitions() {
+        let registry = ElementRegistry::parse("
+            [wax]
+            color = #F0E0B0
+            behaviour = static
+            transition = water above 60
+
+            # water that only freezes next to wax, and never boils
+            [water]
+            transition = ice below 0 touching wax
+        ").unwrap();
+
+        let wax = registry.find("wax").unwrap();
+        assert_eq!(wax.transitions, vec![Transition::new(ElementType::Water).above(degrees(60))]);
+        assert_eq!(registry.transitions(ElementType::Water), [Transition::new(ElementType::Ice).below(degrees(0)).touching(wax.element_type)]);
+        assert_eq!(registry.transitions(ElementType::Ice).len(), 1);
+        assert!(registry.transitions(ElementType::Sand).is_empty());
+    }
+
+    #[test]
+    fn parse_errors_report_lines() {
+        let errors = [
+            ("[goo]\ncolor = #00FF00", 1),
+            ("[goo]\ncolor = #00FF00\nbehaviour = bouncy", 3),
+            ("[sand]\ndensity = 300", 2),
+            ("[sand]\nflavour = salty", 2),
+            ("density = 3", 1),
+            ("[sand\n", 1),
+            ("[sand]\ntransition = lava above 900", 2),
+            ("[sand]\ntransition = water", 2),
+            ("[ice]\n\ntransition = water above warm", 3),
+            ("[frog]\ncolor = #00FF00\nbehaviour = gas", 3),
+        ];
+        for (text, expected_line) in errors {
+            match ElementRegistry::parse(text) {
+                Err(DefinitionError::Parse { line, .. }) => assert_eq!(line, expected_line, "{}", text),
+                _ => panic!("expected a parse error for {:?}", text),
+            }
+        }
+    }
+}
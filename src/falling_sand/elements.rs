@@ -1,13 +1,17 @@
 use rand::Rng;
 
+use super::element_registry::{Behaviour, ElementDefinition};
+
 const STATIC_ELEMENTS: [ElementType; 1] = [ElementType::Wall];
 const SOLID_ELEMENTS: [ElementType; 3] = [ElementType::Sand, ElementType::Dirt, ElementType::Seed];
 const LIQUID_ELEMENTS: [ElementType; 2] = [ElementType::Water, ElementType::Empty];
 const GAS_ELMENTS: [ElementType; 2] = [ElementType::Empty, ElementType::Cloud];
-const LIVING_ELEMENTS: [ElementType; 1] = [ElementType::Grass];
+// elements that can act on their own at any time, even with nothing around
+// them changing, so a chunk holding one never goes to sleep
+const LIVING_ELEMENTS: [ElementType; 13] = [ElementType::Kelp, ElementType::Egg, ElementType::Minnow, ElementType::Tadpole, ElementType::Frog, ElementType::Isopod, ElementType::Snail, ElementType::SpringTail, ElementType::Moss, ElementType::Fire, ElementType::Smoke, ElementType::Cloud, ElementType::Emitter];
 const SPECIAL_ELEMENTS: [ElementType; 0] = [];
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum ElementType {
     Empty,
     Sand,
@@ -27,10 +31,44 @@ pub enum ElementType {
     Snail,
     SpringTail,
     ScreenEdge,
+    Fire,
+    Smoke,
+    Ash,
+    // what's left of plants that have died
+    Detritus,
+    Ice,
+    Steam,
+    Snow,
+    // puts out the element whose id is in its growth, see emitters.rs
+    Emitter,
+    Drain,
+    // elements added by a definitions file, see element_registry
+    Custom(u8),
+}
+
+// ids from here up belong to custom elements
+pub const CUSTOM_ELEMENT_ID_START: u8 = 128;
+
+// Temperatures are kept in tenths of a degree celsius, whole degrees are too
+// coarse for heat to spread evenly (see heat.rs). That covers -3276.8 to
+// 3276.7 degrees.
+pub const TEMPERATURE_SCALE: i16 = 10;
+
+pub const fn degrees(celsius: i16) -> i16 {
+    celsius * TEMPERATURE_SCALE
 }
 
+// what everything starts at unless its definition says otherwise
+pub const AMBIENT_TEMPERATURE: i16 = degrees(20);
 
-#[derive(PartialEq, Copy, Clone)]
+// Velocities are kept in sixteenths of a cell per tick, so gravity can speed
+// things up a little at a time. Nothing goes faster than MAX_SPEED, which is
+// well inside the MAX_REACH an update is allowed (see chunks.rs).
+pub const VELOCITY_SCALE: i8 = 16;
+pub const MAX_SPEED: i8 = 7 * VELOCITY_SCALE;
+
+
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum State {
     Solid,
     Granules,
@@ -39,7 +77,113 @@ pub enum State {
 }
 
 
-#[derive(Copy, Clone)]
+// Stable numeric ids for saving boards. These are written to disk, so never
+// renumber an existing element; new ones get the next free id.
+impl ElementType {
+    pub fn is_living(&self) -> bool {
+        LIVING_ELEMENTS.contains(self)
+    }
+
+    // Whether granule_tick_new updates it with code of its own rather than
+    // by its definition's behaviour.
+    pub fn has_own_update(&self) -> bool {
+        matches!(self, ElementType::Empty | ElementType::Seed | ElementType::Grass | ElementType::Kelp | ElementType::Egg
+            | ElementType::Minnow | ElementType::Fire | ElementType::Smoke | ElementType::Cloud | ElementType::Moss
+            | ElementType::Tadpole | ElementType::Frog | ElementType::Isopod | ElementType::Snail | ElementType::SpringTail
+            | ElementType::Emitter | ElementType::Drain)
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            ElementType::Empty => 0,
+            ElementType::Sand => 1,
+            ElementType::Water => 2,
+            ElementType::Dirt => 3,
+            ElementType::Seed => 4,
+            ElementType::Grass => 5,
+            ElementType::Kelp => 6,
+            ElementType::Wall => 7,
+            ElementType::Moss => 8,
+            ElementType::Cloud => 9,
+            ElementType::Egg => 10,
+            ElementType::Frog => 11,
+            ElementType::Tadpole => 12,
+            ElementType::Isopod => 13,
+            ElementType::Minnow => 14,
+            ElementType::Snail => 15,
+            ElementType::SpringTail => 16,
+            ElementType::ScreenEdge => 17,
+            ElementType::Fire => 18,
+            ElementType::Smoke => 19,
+            ElementType::Ash => 20,
+            ElementType::Detritus => 21,
+            ElementType::Ice => 22,
+            ElementType::Steam => 23,
+            ElementType::Snow => 24,
+            ElementType::Emitter => 25,
+            ElementType::Drain => 26,
+            ElementType::Custom(index) => CUSTOM_ELEMENT_ID_START + index,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<ElementType> {
+        match id {
+            0 => Some(ElementType::Empty),
+            1 => Some(ElementType::Sand),
+            2 => Some(ElementType::Water),
+            3 => Some(ElementType::Dirt),
+            4 => Some(ElementType::Seed),
+            5 => Some(ElementType::Grass),
+            6 => Some(ElementType::Kelp),
+            7 => Some(ElementType::Wall),
+            8 => Some(ElementType::Moss),
+            9 => Some(ElementType::Cloud),
+            10 => Some(ElementType::Egg),
+            11 => Some(ElementType::Frog),
+            12 => Some(ElementType::Tadpole),
+            13 => Some(ElementType::Isopod),
+            14 => Some(ElementType::Minnow),
+            15 => Some(ElementType::Snail),
+            16 => Some(ElementType::SpringTail),
+            17 => Some(ElementType::ScreenEdge),
+            18 => Some(ElementType::Fire),
+            19 => Some(ElementType::Smoke),
+            20 => Some(ElementType::Ash),
+            21 => Some(ElementType::Detritus),
+            22 => Some(ElementType::Ice),
+            23 => Some(ElementType::Steam),
+            24 => Some(ElementType::Snow),
+            25 => Some(ElementType::Emitter),
+            26 => Some(ElementType::Drain),
+            CUSTOM_ELEMENT_ID_START..=u8::MAX => Some(ElementType::Custom(id - CUSTOM_ELEMENT_ID_START)),
+            _ => None,
+        }
+    }
+}
+
+impl State {
+    pub fn id(&self) -> u8 {
+        match self {
+            State::Solid => 0,
+            State::Granules => 1,
+            State::Liquid => 2,
+            State::Gas => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<State> {
+        match id {
+            0 => Some(State::Solid),
+            1 => Some(State::Granules),
+            2 => Some(State::Liquid),
+            3 => Some(State::Gas),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub struct Element {
     pub element_type: ElementType,
     pub state: State,
@@ -48,146 +192,47 @@ pub struct Element {
     pub hunger: u8,
     pub growth: u8,
     pub direction: bool,
-
+    // tenths of a degree celsius
+    pub temperature: i16,
+    // sixteenths of a cell per tick, positive is right and down
+    pub velocity_x: i8,
+    pub velocity_y: i8,
 }
 
 impl Element {
-    pub fn new(element_type: ElementType) -> Self {
-        let mut rng_seed = rand::thread_rng();
-        match element_type {
-            ElementType::Empty => {
-                return Self {
-                    element_type: ElementType::Empty,
-                    state: State::Gas,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Sand => {
-                return Self {
-                    element_type: ElementType::Sand,
-                    state: State::Granules,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Water => {
-                return Self {
-                    element_type: ElementType::Water,
-                    state: State::Liquid,
-                    update_toggle: false,
-                    moisture: 255,
-                    hunger: 0,
-                    growth: 0,
-                    direction: rng_seed.gen_bool(0.5),
-                }
-            },
-            ElementType::Dirt => {
-                return Self {
-                    element_type: ElementType::Dirt,
-                    state: State::Granules,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Wall => {
-                return Self {
-                    element_type: ElementType::Wall,
-                    state: State::Solid,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Seed => {
-                return Self {
-                    element_type: ElementType::Seed,
-                    state: State::Granules,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Grass => {
-                return Self {
-                    element_type: ElementType::Grass,
-                    state: State::Solid,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Kelp => {
-                return Self {
-                    element_type: ElementType::Kelp,
-                    state: State::Solid,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Egg => {
-                return Self {
-                    element_type: ElementType::Egg,
-                    state: State::Granules,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::Minnow => {
-                return Self {
-                    element_type: ElementType::Minnow,
-                    state: State::Granules,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-            ElementType::ScreenEdge => {
-                return Self {
-                    element_type: ElementType::ScreenEdge,
-                    state: State::Solid,
-                    update_toggle: false,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            },
-
-            _ => {
-                return Self {
-                    element_type: ElementType::Empty,
-                    update_toggle: false,
-                    state: State::Gas,
-                    moisture: 0,
-                    hunger: 0,
-                    growth: 0,
-                    direction: false,
-                }
-            }
+    // Creates a fresh element from its definition, rolling any per-granule
+    // randomness from `rng`. Boards pass their own seeded rng here so runs
+    // stay reproducible.
+    pub fn new<R: Rng + ?Sized>(definition: &ElementDefinition, rng: &mut R) -> Self {
+        let mut element = Self::blank(definition.element_type);
+        element.state = definition.state;
+        element.moisture = definition.moisture;
+        element.growth = definition.growth;
+        element.temperature = definition.temperature;
+        if definition.behaviour == Behaviour::Liquid || definition.behaviour == Behaviour::Gas {
+            element.direction = rng.gen_bool(0.5);
+        }
+        element
+    }
+
+    // An element with no properties of its own. Used for placeholder cells
+    // (empty space, the screen edge) where there's no registry at hand.
+    pub fn blank(element_type: ElementType) -> Self {
+        let mut state = State::Solid;
+        if element_type == ElementType::Empty {
+            state = State::Gas;
+        }
+        Self {
+            element_type,
+            state,
+            update_toggle: false,
+            moisture: 0,
+            hunger: 0,
+            growth: 0,
+            direction: false,
+            temperature: AMBIENT_TEMPERATURE,
+            velocity_x: 0,
+            velocity_y: 0,
         }
     }
 
@@ -200,11 +245,3 @@ impl Element {
         }
     }
 }
-
-
-//const ElementProperties [element; 7] = [
-  //  Element {
-  //      element_type: Sand,
-  //      i
- //   }
-//]
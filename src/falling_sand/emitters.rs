@@ -0,0 +1,129 @@
+// Emitters and drains. An emitter is a solid block that keeps putting out an
+// element into the empty cells on its four sides, EMIT_CHANCE of the time
+// each tick. Which element is kept in its growth, water unless it was painted
+// with add_emitters, and is saved along with the rest of the granule.
+//
+// A drain deletes anything that touches it, on any of its eight sides, walls
+// and emitters included. Only other drains are left, or a patch of them would
+// eat itself.
+//
+// Both set their update_toggle every time they update. Being static they'd
+// otherwise only get a turn every other tick, and it keeps a cell that's been
+// moved into from updating twice in one tick. Emitters are living, since a
+// roll that doesn't come up changes nothing, and the chunk would otherwise
+// sleep with an emitter in it that still has room to fill.
+
+use rand::Rng;
+
+use super::SandBoard;
+use super::elements::ElementType;
+use super::update_functions::NEIGHBOURS;
+
+const EMIT_CHANCE: f64 = 0.25;
+
+
+impl SandBoard {
+    // Paints emitters like add_granules, all of them putting out `emits`.
+    pub fn add_emitters(&mut self, x: usize, y: usize, radius: usize, emits: ElementType) -> usize {
+        let added_granules = self.add_granules(x, y, radius, ElementType::Emitter);
+        for granule_y in y.saturating_sub(radius)..(y + radius + 1).min(self.height) {
+            for granule_x in x.saturating_sub(radius)..(x + radius + 1).min(self.width) {
+                let index = self.get_index_from_coordinates(granule_x, granule_y);
+                let is_painted = SandBoard::get_distance((granule_x as f64, granule_y as f64), (x as f64, y as f64)) < radius as f64;
+                if is_painted && self.granules[index].element_type == ElementType::Emitter {
+                    let before = self.granules[index];
+                    self.granules[index].growth = emits.id();
+                    self.history.record(index, before, self.granules[index]);
+                }
+            }
+        }
+        added_granules
+    }
+
+    pub(super) fn update_emitter(&mut self, index: usize) {
+        self.granules[index].update_toggle = self.update_cycle;
+        let Some(emits) = ElementType::from_id(self.granules[index].growth) else {
+            return
+        };
+        for (x_relative, y_relative) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            let Some(side_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            if self.granules[side_index].element_type == ElementType::Empty && self.rng.gen_bool(EMIT_CHANCE) {
+                self.granules[side_index] = self.new_element(emits);
+                self.granules[side_index].update_toggle = self.update_cycle;
+            }
+        }
+    }
+
+    pub(super) fn update_drain(&mut self, index: usize) {
+        self.granules[index].update_toggle = self.update_cycle;
+        for (x_relative, y_relative) in NEIGHBOURS {
+            let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            let neighbour_type = self.granules[neighbour_index].element_type;
+            if neighbour_type != ElementType::Empty && neighbour_type != ElementType::Drain {
+                self.granules[neighbour_index] = self.new_element(ElementType::Empty);
+                self.granules[neighbour_index].update_toggle = self.update_cycle;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod emitters_test {
+    use super::*;
+
+    fn count(sand_board: &SandBoard, element_type: ElementType) -> usize {
+        sand_board.granules.iter().filter(|granule| granule.element_type == element_type).count()
+    }
+
+    #[test]
+    fn emitted_sand_runs_into_a_drain() {
+        let mut sand_board = SandBoard::with_seed(32, 32, 8);
+        sand_board.add_emitters(16, 4, 1, ElementType::Sand);
+        assert_eq!(sand_board.granules[sand_board.get_index_from_coordinates(16, 4)].growth, ElementType::Sand.id());
+        for _ in 0..100 {
+            sand_board.tick();
+        }
+        let piled_up = count(&sand_board, ElementType::Sand);
+        assert!(piled_up > 20, "only {} sand", piled_up);
+
+        // a floor of drains takes everything that lands on it
+        for x in 0..32 {
+            sand_board.add_granule(x, 31, ElementType::Drain);
+        }
+        sand_board.add_granules(16, 4, 1, ElementType::Empty);
+        for _ in 0..200 {
+            sand_board.tick();
+        }
+        assert_eq!(count(&sand_board, ElementType::Sand), 0);
+        assert_eq!(count(&sand_board, ElementType::Drain), 32);
+    }
+
+    #[test]
+    fn emitters_fire_once_a_tick_and_drains_take_walls() {
+        let mut sand_board = SandBoard::with_seed(32, 32, 9);
+        sand_board.add_granule(16, 16, ElementType::Emitter);
+        sand_board.add_granule(4, 4, ElementType::Drain);
+        sand_board.add_granule(5, 4, ElementType::Wall);
+        sand_board.add_granule(4, 5, ElementType::Drain);
+        // a freshly painted emitter puts out water
+        let mut emitted = Vec::new();
+        for _ in 0..40 {
+            // counted as a difference, since a little may evaporate
+            let before = count(&sand_board, ElementType::Water) as i64;
+            sand_board.tick();
+            emitted.push(count(&sand_board, ElementType::Water) as i64 - before);
+        }
+        // never more than its four sides' worth in a tick, and it fires on
+        // every tick rather than every other one
+        assert!(emitted.iter().all(|water| *water <= 4));
+        assert!(emitted.iter().step_by(2).sum::<i64>() > 0);
+        assert!(emitted.iter().skip(1).step_by(2).sum::<i64>() > 0);
+        assert_eq!(count(&sand_board, ElementType::Wall), 0);
+        assert_eq!(count(&sand_board, ElementType::Drain), 2);
+    }
+}
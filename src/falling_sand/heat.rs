@@ -0,0 +1,185 @@
+// Heat. Every cell has a temperature, and each tick heat flows between a
+// cell and the four cells next to it. How much flows depends on the lower
+// of the two conductivities, and the cell's heat capacity slows down how
+// fast its own temperature follows. Granules that move take their
+// temperature with them.
+//
+// Temperatures are whole tenths of a degree, so heat stops flowing once
+// neighbours are close enough that a tick's worth of flow rounds to nothing.
+// That leaves small differences behind (under a degree through most things,
+// a bit more across air), but it means a board that has finished warming up
+// stops changing and its chunks can sleep.
+
+use super::SandBoard;
+
+// Flow per tick is difference * conductivity / (HEAT_FLOW_DIVISOR *
+// heat_capacity). With four neighbours at conductivity 255 that's still less
+// than the difference itself, so a cell never overshoots its neighbours.
+const HEAT_FLOW_DIVISOR: i32 = 1024;
+
+
+impl SandBoard {
+    pub fn get_temperature(&self, x: usize, y: usize) -> Option<i16> {
+        if !self.is_in_coordinate_bounds(x, y) {
+            return None
+        }
+        let index = self.get_index_from_coordinates(x, y);
+        Some(self.granules[index].temperature)
+    }
+
+    // Temperatures of a width x height rectangle, row by row from its top
+    // left corner. None if the rectangle doesn't fit on the board.
+    pub fn get_temperature_region(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Vec<i16>> {
+        if x + width > self.width || y + height > self.height {
+            return None
+        }
+        let mut temperatures = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            let row_start = self.get_index_from_coordinates(x, row);
+            temperatures.extend(self.granules[row_start..row_start + width].iter().map(|granule| granule.temperature));
+        }
+        Some(temperatures)
+    }
+
+    // Sets every cell in the rectangle to `temperature`, whatever is in it.
+    // The parts of the rectangle that are off the board are ignored.
+    pub fn set_temperature_region(&mut self, x: usize, y: usize, width: usize, height: usize, temperature: i16) {
+        let x_end = (x + width).min(self.width);
+        let y_end = (y + height).min(self.height);
+        if x >= x_end || y >= y_end {
+            return
+        }
+        for row in y..y_end {
+            for column in x..x_end {
+                let index = self.get_index_from_coordinates(column, row);
+                self.granules[index].temperature = temperature;
+            }
+        }
+        self.wake_region(x, y, x_end - 1, y_end - 1);
+    }
+
+    // One step of heat flow over the given chunks. Every new temperature is
+    // worked out from the temperatures before the step, so the order the
+    // chunks are done in (and how many threads do them) doesn't matter.
+    pub(super) fn diffuse_heat(&mut self, chunks: &[usize]) {
+        let diffused = self.run_jobs(chunks.to_vec(), |chunk| self.diffused_chunk(chunk));
+        for (chunk, temperatures) in chunks.iter().zip(diffused) {
+            let Some(temperatures) = temperatures else {
+                continue
+            };
+            let (x_start, y_start, x_end, y_end) = self.chunk_bounds(*chunk);
+            let mut temperatures = temperatures.into_iter();
+            for y in y_start..y_end {
+                for x in x_start..x_end {
+                    let index = self.get_index_from_coordinates(x, y);
+                    self.granules[index].temperature = temperatures.next().unwrap();
+                }
+            }
+        }
+    }
+
+    // None if nothing in the chunk changes temperature this tick
+    fn diffused_chunk(&self, chunk: usize) -> Option<Vec<i16>> {
+        let (x_start, y_start, x_end, y_end) = self.chunk_bounds(chunk);
+        if self.is_even_temperature(x_start.saturating_sub(1), y_start.saturating_sub(1), (x_end + 1).min(self.width), (y_end + 1).min(self.height)) {
+            return None
+        }
+        let mut temperatures = Vec::with_capacity((x_end - x_start) * (y_end - y_start));
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                temperatures.push(self.diffused_temperature(x, y));
+            }
+        }
+        Some(temperatures)
+    }
+
+    // Most of a settled board is all one temperature, and no heat can flow
+    // anywhere in a patch like that
+    fn is_even_temperature(&self, x_start: usize, y_start: usize, x_end: usize, y_end: usize) -> bool {
+        let temperature = self.granules[self.get_index_from_coordinates(x_start, y_start)].temperature;
+        (y_start..y_end).all(|y| {
+            let row_start = self.get_index_from_coordinates(x_start, y);
+            self.granules[row_start..row_start + x_end - x_start].iter().all(|granule| granule.temperature == temperature)
+        })
+    }
+
+    fn diffused_temperature(&self, x: usize, y: usize) -> i16 {
+        let granule = self.granules[self.get_index_from_coordinates(x, y)];
+        // the board's edges don't let heat through
+        let mut neighbours = [None; 4];
+        if x > 0 {
+            neighbours[0] = Some(self.get_index_from_coordinates(x - 1, y));
+        }
+        if x + 1 < self.width {
+            neighbours[1] = Some(self.get_index_from_coordinates(x + 1, y));
+        }
+        if y > 0 {
+            neighbours[2] = Some(self.get_index_from_coordinates(x, y - 1));
+        }
+        if y + 1 < self.height {
+            neighbours[3] = Some(self.get_index_from_coordinates(x, y + 1));
+        }
+
+        let (conductivity, heat_capacity) = self.registry.heat_properties(granule.element_type);
+        let mut flow: i32 = 0;
+        for neighbour in neighbours.into_iter().flatten() {
+            let other = self.granules[neighbour];
+            if other.temperature == granule.temperature {
+                continue
+            }
+            let (other_conductivity, _) = self.registry.heat_properties(other.element_type);
+            let difference = other.temperature as i32 - granule.temperature as i32;
+            flow += difference * conductivity.min(other_conductivity) as i32;
+        }
+        if flow == 0 {
+            return granule.temperature
+        }
+
+        // rounded to the nearest tenth rather than towards zero, so heat
+        // doesn't get stuck behind differences bigger than it needs to
+        let divisor = HEAT_FLOW_DIVISOR * heat_capacity as i32;
+        let change = (flow + flow.signum() * divisor / 2) / divisor;
+        (granule.temperature as i32 + change).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+
+#[cfg(test)]
+mod heat_test {
+    use super::*;
+    use super::super::elements::{degrees, ElementType, AMBIENT_TEMPERATURE};
+
+    #[test]
+    fn heat_spreads_and_settles() {
+        let mut sand_board = SandBoard::with_seed(40, 40, 8);
+        sand_board.add_granules(20, 20, 10, ElementType::Wall);
+        sand_board.set_temperature_region(18, 18, 5, 5, degrees(500));
+        let total_before: i32 = sand_board.get_temperature_region(10, 10, 21, 21).unwrap().iter().map(|t| *t as i32).sum();
+
+        sand_board.tick();
+        assert!(sand_board.get_temperature(18, 20).unwrap() < degrees(500));
+        assert!(sand_board.get_temperature(17, 20).unwrap() > AMBIENT_TEMPERATURE);
+
+        for _ in 0..2000 {
+            sand_board.tick();
+        }
+        // the wall evens out, and only a little heat leaks into the air
+        let wall = sand_board.get_temperature_region(15, 15, 11, 11).unwrap();
+        let (min, max) = (*wall.iter().min().unwrap(), *wall.iter().max().unwrap());
+        assert!(max - min <= degrees(10), "{} to {}", min, max);
+        let total_after: i32 = sand_board.get_temperature_region(10, 10, 21, 21).unwrap().iter().map(|t| *t as i32).sum();
+        assert!((total_after - total_before).abs() < total_before / 10);
+        assert_eq!(sand_board.awake_chunk_count(), 0);
+    }
+
+    #[test]
+    fn temperature_region_bounds() {
+        let mut sand_board = SandBoard::with_seed(10, 10, 1);
+        assert!(sand_board.get_temperature_region(5, 5, 6, 1).is_none());
+        assert_eq!(sand_board.get_temperature(10, 0), None);
+
+        sand_board.set_temperature_region(8, 8, 5, 5, degrees(-40));
+        assert_eq!(sand_board.get_temperature_region(8, 8, 2, 2).unwrap(), vec![degrees(-40); 4]);
+        assert_eq!(sand_board.get_temperature(7, 8), Some(AMBIENT_TEMPERATURE));
+    }
+}
@@ -0,0 +1,330 @@
+// Undo and redo. Painting with add_granules (or add_emitters) records every
+// cell it changes, with what was in it before and after, as one edit.
+// Painting keeps adding to the same edit until finish_edit is called, so the
+// game closes it when the mouse is let go and a whole stroke comes off in one
+// undo. Undoing puts back what was in the stroke's cells before it, and
+// redoing what it left there, over whatever the board has done to those cells
+// since. A reset keeps a copy of the board from before it instead, which is
+// under half the size of a change for every cell.
+//
+// The history is kept within a budget of bytes (DEFAULT_HISTORY_BUDGET, or
+// set_history_budget), and the oldest edits are dropped to stay inside it.
+// An edit bigger than the whole budget can't be undone at all. Loading a
+// board starts its history afresh.
+//
+// hold_history keeps undo and redo to the edits made after it, until
+// release_history. A recording holds it, since its replay starts out without
+// the edits from before.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::SandBoard;
+use super::elements::{Element, ElementType};
+
+pub const DEFAULT_HISTORY_BUDGET: usize = 16 * 1024 * 1024;
+
+const CHANGE_SIZE: usize = std::mem::size_of::<CellChange>();
+const ELEMENT_SIZE: usize = std::mem::size_of::<Element>();
+
+
+#[derive(Copy, Clone)]
+struct CellChange {
+    index: usize,
+    before: Element,
+    after: Element,
+}
+
+enum Edit {
+    Cells(Vec<CellChange>),
+    // the board as it was before a reset emptied it
+    Reset(Vec<Element>),
+}
+
+impl Edit {
+    // bytes it counts for against the budget
+    fn size(&self) -> usize {
+        match self {
+            Edit::Cells(changes) => changes.len() * CHANGE_SIZE,
+            Edit::Reset(board) => board.len() * ELEMENT_SIZE,
+        }
+    }
+}
+
+pub(crate) struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    // the edit still being painted, and where each of its cells is in it
+    open: Vec<CellChange>,
+    open_cells: HashMap<usize, usize>,
+    budget: usize,
+    // bytes used by the changes in undo and redo
+    used: usize,
+    // how many of the oldest edits in undo and redo are held back
+    held_undo: usize,
+    held_redo: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            open: Vec::new(),
+            open_cells: HashMap::new(),
+            budget: DEFAULT_HISTORY_BUDGET,
+            used: 0,
+            held_undo: 0,
+            held_redo: 0,
+        }
+    }
+}
+
+impl History {
+    // Adds a cell to the open edit. A cell already in it keeps what it had
+    // before the edit started.
+    pub(super) fn record(&mut self, index: usize, before: Element, after: Element) {
+        if let Some(position) = self.open_cells.get(&index) {
+            self.open[*position].after = after;
+            return
+        }
+        if before == after {
+            return
+        }
+        if self.open.is_empty() {
+            self.clear_redo();
+        }
+        self.open_cells.insert(index, self.open.len());
+        self.open.push(CellChange { index, before, after });
+    }
+
+    fn finish(&mut self) {
+        self.open_cells.clear();
+        if self.open.is_empty() {
+            return
+        }
+        let edit = Edit::Cells(std::mem::take(&mut self.open));
+        self.push(edit);
+    }
+
+    // Keeps the board from before a reset, as an edit of its own.
+    pub(super) fn record_reset(&mut self, board: Vec<Element>) {
+        self.clear_redo();
+        self.push(Edit::Reset(board));
+    }
+
+    fn push(&mut self, edit: Edit) {
+        self.used += edit.size();
+        self.undo.push_back(edit);
+        self.trim();
+    }
+
+    // a new edit makes anything undone unredoable
+    fn clear_redo(&mut self) {
+        self.used -= self.redo.iter().map(Edit::size).sum::<usize>();
+        self.redo.clear();
+        self.held_redo = 0;
+    }
+
+    fn trim(&mut self) {
+        while self.used > self.budget {
+            let edit = if let Some(edit) = self.undo.pop_front() {
+                self.held_undo = self.held_undo.saturating_sub(1);
+                edit
+            }
+            else if let Some(edit) = self.redo.pop() {
+                self.held_redo = self.held_redo.min(self.redo.len());
+                edit
+            }
+            else {
+                break
+            };
+            self.used -= edit.size();
+        }
+    }
+}
+
+
+impl SandBoard {
+    // Closes the edit painting has been adding to, so the next stroke is
+    // undone on its own.
+    pub fn finish_edit(&mut self) {
+        self.history.finish();
+    }
+
+    // Takes back the last edit. Returns false if there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.history.finish();
+        if self.history.undo.len() <= self.history.held_undo {
+            return false
+        }
+        let Some(edit) = self.history.undo.pop_back() else {
+            return false
+        };
+        match &edit {
+            Edit::Cells(changes) => {
+                for change in changes.iter().rev() {
+                    self.granules[change.index] = change.before;
+                }
+            },
+            // like a stroke, only the cells the reset changed are put back
+            Edit::Reset(board) => {
+                for (index, granule) in board.iter().enumerate() {
+                    if *granule != Element::blank(ElementType::Empty) {
+                        self.granules[index] = *granule;
+                    }
+                }
+            },
+        }
+        self.wake_edit(&edit);
+        self.history.redo.push(edit);
+        true
+    }
+
+    // Puts back the last edit undone. Returns false if there's nothing to
+    // redo.
+    pub fn redo(&mut self) -> bool {
+        self.history.finish();
+        if self.history.redo.len() <= self.history.held_redo {
+            return false
+        }
+        let Some(edit) = self.history.redo.pop() else {
+            return false
+        };
+        match &edit {
+            Edit::Cells(changes) => {
+                for change in changes.iter() {
+                    self.granules[change.index] = change.after;
+                }
+            },
+            Edit::Reset(_) => self.empty_board(),
+        }
+        self.wake_edit(&edit);
+        self.history.undo.push_back(edit);
+        true
+    }
+
+    // Stops undo and redo reaching any edit made before now.
+    pub fn hold_history(&mut self) {
+        self.history.finish();
+        self.history.held_undo = self.history.undo.len();
+        self.history.held_redo = self.history.redo.len();
+    }
+
+    pub fn release_history(&mut self) {
+        self.history.held_undo = 0;
+        self.history.held_redo = 0;
+    }
+
+    pub fn history_budget(&self) -> usize {
+        self.history.budget
+    }
+
+    // How many bytes of edits are kept for undo and redo.
+    pub fn set_history_budget(&mut self, bytes: usize) {
+        self.history.budget = bytes;
+        self.history.trim();
+    }
+
+    fn wake_edit(&mut self, edit: &Edit) {
+        let Edit::Cells(changes) = edit else {
+            self.wake_all();
+            return
+        };
+        let Some((x_start, y_start, x_end, y_end)) = changes.iter()
+            .map(|change| self.get_coordinates_from_index(change.index))
+            .fold(None, |bounds: Option<(usize, usize, usize, usize)>, (x, y)| match bounds {
+                Some((x_start, y_start, x_end, y_end)) => Some((x_start.min(x), y_start.min(y), x_end.max(x), y_end.max(y))),
+                None => Some((x, y, x, y)),
+            }) else {
+            return
+        };
+        self.wake_region(x_start, y_start, x_end, y_end);
+    }
+}
+
+
+#[cfg(test)]
+mod history_test {
+    use super::*;
+
+    fn count(sand_board: &SandBoard, element_type: ElementType) -> usize {
+        sand_board.granules.iter().filter(|granule| granule.element_type == element_type).count()
+    }
+
+    #[test]
+    fn strokes_undo_and_redo_whole() {
+        let mut sand_board = SandBoard::with_seed(40, 40, 3);
+        for x in 5..35 {
+            sand_board.add_granules(x, 20, 3, ElementType::Wall);
+        }
+        sand_board.finish_edit();
+        let walls = sand_board.granules.clone();
+        let wall_count = count(&sand_board, ElementType::Wall);
+        // one stroke of the eraser across the middle, dragged over itself
+        for x in (10..30).chain((10..30).rev()) {
+            sand_board.add_granules(x, 20, 4, ElementType::Empty);
+            sand_board.tick();
+        }
+        assert!(count(&sand_board, ElementType::Wall) < wall_count / 2);
+
+        assert!(sand_board.undo());
+        assert!(sand_board.granules == walls);
+        assert!(sand_board.redo());
+        assert!(count(&sand_board, ElementType::Wall) < wall_count / 2);
+        assert!(!sand_board.redo());
+
+        assert!(sand_board.undo());
+        assert!(sand_board.undo());
+        assert_eq!(count(&sand_board, ElementType::Wall), 0);
+        assert!(!sand_board.undo());
+
+        // painting after an undo throws away what could have been redone
+        sand_board.add_granules(20, 20, 2, ElementType::Wall);
+        assert!(!sand_board.redo());
+    }
+
+    #[test]
+    fn resets_undo_and_old_edits_drop_off_the_budget() {
+        let mut sand_board = SandBoard::with_seed(40, 40, 4);
+        sand_board.randomize_ocean();
+        let ocean = sand_board.granules.clone();
+        sand_board.reset();
+        assert_eq!(count(&sand_board, ElementType::Empty), 40 * 40);
+        assert!(sand_board.undo());
+        assert!(sand_board.granules == ocean);
+
+        // room for two strokes of nine cells
+        sand_board.set_history_budget(2 * 9 * CHANGE_SIZE);
+        assert!(!sand_board.undo());
+        for x in [5, 15, 25] {
+            sand_board.add_granules(x, 5, 2, ElementType::Wall);
+            sand_board.finish_edit();
+        }
+        assert!(sand_board.undo());
+        assert!(sand_board.undo());
+        assert!(!sand_board.undo());
+    }
+
+    #[test]
+    fn resets_of_boards_too_big_to_record_cell_by_cell_undo() {
+        let mut sand_board = SandBoard::with_seed(1000, 1000, 5);
+        for index in 0..sand_board.granules.len() {
+            sand_board.granules[index] = Element::blank(ElementType::Sand);
+        }
+        assert!(sand_board.granules.len() * CHANGE_SIZE > DEFAULT_HISTORY_BUDGET);
+        let sand = sand_board.granules.clone();
+        sand_board.add_granules(10, 10, 3, ElementType::Wall);
+        let painted = sand_board.granules.clone();
+        sand_board.reset();
+        assert_eq!(count(&sand_board, ElementType::Sand), 0);
+
+        assert!(sand_board.undo());
+        assert!(sand_board.granules == painted);
+        assert!(sand_board.redo());
+        assert_eq!(count(&sand_board, ElementType::Empty), 1000 * 1000);
+        // the stroke before the reset is still there too
+        assert!(sand_board.undo());
+        assert!(sand_board.undo());
+        assert!(sand_board.granules == sand);
+    }
+}
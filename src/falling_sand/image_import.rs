@@ -0,0 +1,148 @@
+// Building boards from images, so levels can be drawn in an image editor.
+// Every pixel becomes the element whose palette color is closest to it.
+
+use std::path::Path;
+
+use image::RgbaImage;
+
+use super::*;
+use super::elements::ElementType;
+use super::element_registry::ElementRegistry;
+
+// Squared RGB distance a pixel may be from its nearest palette color and still
+// count as that element. Anything further away is reported as unmapped and
+// left empty, so stray anti-aliased edges don't turn into random material.
+pub const IMAGE_MATCH_TOLERANCE: u32 = 40 * 40 * 3;
+
+// pixels more transparent than this are empty space, and aren't reported
+const IMAGE_ALPHA_CUTOFF: u8 = 128;
+
+
+#[derive(Debug, PartialEq)]
+pub struct UnmappedColor {
+    pub color: [u8; 4],
+    pub pixel_count: usize,
+    // the closest palette entry, to help track down typos in the drawing
+    pub nearest: ElementType,
+}
+
+#[derive(Debug, Default)]
+pub struct ImageImportReport {
+    pub unmapped_colors: Vec<UnmappedColor>,
+}
+
+impl ImageImportReport {
+    pub fn unmapped_pixel_count(&self) -> usize {
+        self.unmapped_colors.iter().map(|unmapped| unmapped.pixel_count).sum()
+    }
+}
+
+
+// The colors the board is drawn with, so a screenshot imports back as the
+// same board. Boards using a definitions file should use that registry's
+// palette() instead.
+pub fn default_palette() -> Vec<(ElementType, [u8; 4])> {
+    ElementRegistry::default().palette()
+}
+
+fn color_distance(first: [u8; 4], second: [u8; 4]) -> u32 {
+    let mut distance = 0;
+    for channel in 0..3 {
+        let difference = first[channel] as i32 - second[channel] as i32;
+        distance += (difference * difference) as u32;
+    }
+    distance
+}
+
+fn nearest_element(color: [u8; 4], palette: &[(ElementType, [u8; 4])]) -> Option<(ElementType, u32)> {
+    palette.iter()
+        .map(|(element_type, palette_color)| (*element_type, color_distance(color, *palette_color)))
+        .min_by_key(|(_, distance)| *distance)
+}
+
+
+impl SandBoard {
+    // Loads an image (anything the image crate can decode) as a board the size
+    // of the image. `palette` maps colors to elements; `default_palette()`
+    // matches the colors the game draws with.
+    pub fn from_image<P: AsRef<Path>>(path: P, palette: &[(ElementType, [u8; 4])]) -> image::ImageResult<(SandBoard, ImageImportReport)> {
+        let image = image::open(path)?.to_rgba8();
+        let seed = rand::thread_rng().gen();
+        Ok(SandBoard::from_rgba_image(&image, palette, ElementRegistry::shared_default(), seed))
+    }
+
+    // Like from_image, but for boards using a definitions file: colors are
+    // matched against that registry's elements, custom ones included.
+    pub fn from_image_with_registry<P: AsRef<Path>>(path: P, registry: Arc<ElementRegistry>) -> image::ImageResult<(SandBoard, ImageImportReport)> {
+        let image = image::open(path)?.to_rgba8();
+        let seed = rand::thread_rng().gen();
+        let palette = registry.palette();
+        Ok(SandBoard::from_rgba_image(&image, &palette, registry, seed))
+    }
+
+    pub fn from_rgba_image(image: &RgbaImage, palette: &[(ElementType, [u8; 4])], registry: Arc<ElementRegistry>, seed: u64) -> (SandBoard, ImageImportReport) {
+        let mut sand_board = SandBoard::with_seed(image.width() as usize, image.height() as usize, seed);
+        sand_board.set_registry(registry);
+        let mut report = ImageImportReport::default();
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let color = pixel.0;
+            if color[3] < IMAGE_ALPHA_CUTOFF {
+                continue
+            }
+            let element_type = match nearest_element(color, palette) {
+                Some((element_type, distance)) if distance <= IMAGE_MATCH_TOLERANCE => element_type,
+                nearest => {
+                    let nearest = nearest.map_or(ElementType::Empty, |(element_type, _)| element_type);
+                    match report.unmapped_colors.iter_mut().find(|unmapped| unmapped.color == color) {
+                        Some(unmapped) => unmapped.pixel_count += 1,
+                        None => report.unmapped_colors.push(UnmappedColor { color, pixel_count: 1, nearest }),
+                    }
+                    continue
+                }
+            };
+            let index = sand_board.get_index_from_coordinates(x as usize, y as usize);
+            sand_board.granules[index] = sand_board.new_element(element_type);
+        }
+
+        report.unmapped_colors.sort_by_key(|unmapped| std::cmp::Reverse(unmapped.pixel_count));
+        (sand_board, report)
+    }
+}
+
+
+#[cfg(test)]
+mod image_import_test {
+    use super::*;
+
+    #[test]
+    fn screenshot_imports_as_same_board() {
+        let mut sand_board = SandBoard::with_seed(40, 30, 5);
+        sand_board.randomize_ocean();
+        sand_board.add_granules(10, 10, 3, ElementType::Wall);
+        sand_board.add_granules(30, 10, 3, ElementType::Dirt);
+
+        let image = sand_board.render_to_image(1).unwrap();
+        let (imported, report) = SandBoard::from_rgba_image(&image, &default_palette(), ElementRegistry::shared_default(), 5);
+        assert!(report.unmapped_colors.is_empty());
+        for i in 0..sand_board.granules.len() {
+            assert_eq!(imported.granules[i].element_type, sand_board.granules[i].element_type);
+        }
+    }
+
+    #[test]
+    fn near_colors_snap_and_far_colors_are_reported() {
+        let mut image = RgbaImage::new(3, 1);
+        image.put_pixel(0, 0, image::Rgba([0xC0, 0xC8, 0x10, 0xFF]));
+        image.put_pixel(1, 0, image::Rgba([0xFF, 0x00, 0xFF, 0xFF]));
+        image.put_pixel(2, 0, image::Rgba([0xFF, 0x00, 0xFF, 0x00]));
+        let (imported, report) = SandBoard::from_rgba_image(&image, &default_palette(), ElementRegistry::shared_default(), 1);
+
+        assert_eq!(imported.granules[0].element_type, ElementType::Sand);
+        assert_eq!(imported.granules[1].element_type, ElementType::Empty);
+        assert_eq!(imported.granules[2].element_type, ElementType::Empty);
+        assert_eq!(report.unmapped_colors.len(), 1);
+        assert_eq!(report.unmapped_colors[0].color, [0xFF, 0x00, 0xFF, 0xFF]);
+        assert_eq!(report.unmapped_pixel_count(), 1);
+    }
+}
@@ -1,59 +1,145 @@
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use std::sync::Arc;
 use winit::event::ElementState;
 
 pub mod elements;
+mod cells;
 mod update_functions;
 pub mod screen_texture;
+pub mod save_file;
+pub mod image_import;
+pub mod element_registry;
+pub mod chunks;
+pub mod heat;
+pub mod pressure;
+pub mod transitions;
+pub mod edges;
+pub mod emitters;
+pub mod history;
+pub mod replay;
+pub mod weather;
 
 use crate::falling_sand::screen_texture::*;
 use crate::falling_sand::elements::*;
+use crate::falling_sand::element_registry::*;
+use crate::falling_sand::cells::Cells;
+use crate::falling_sand::chunks::ChunkMap;
+use crate::falling_sand::pressure::LevelSearch;
+use crate::falling_sand::history::History;
+use crate::falling_sand::edges::{EdgeMode, Edges};
 
 // use crate::falling_sand::
 
 
 pub struct SandBoard {
-    granules: Vec<Element>,
+    granules: Cells,
     pub width: usize,
     pub height: usize,
-    pub is_floor: bool, 
+    edges: Edges,
     pub right_sand: usize,
     pub left_sand: usize,
     pub unchanged: usize,
     pub unaccounted: usize,
     update_cycle: bool,
+    seed: u64,
+    // every random choice the board makes (element updates, new elements,
+    // scenarios) is drawn from here so a seed fully determines a run
+    rng: StdRng,
+    registry: Arc<ElementRegistry>,
+    chunks: ChunkMap,
+    level_search: LevelSearch,
+    // painting and resets, for undo and redo
+    history: History,
+    threads: usize,
 }
 
 impl SandBoard {
     pub fn new(width: usize, height: usize) -> Self {
+        let seed = rand::thread_rng().gen();
+        Self::with_seed(width, height, seed)
+    }
+
+    pub fn with_seed(width: usize, height: usize, seed: u64) -> Self {
         let indices = width * height;
         let mut granules = Vec::new();
 
-        for i in 0..indices {
-            let element = Element::new(ElementType::Empty);
+        for _ in 0..indices {
+            let element = Element::blank(ElementType::Empty);
             granules.push(element);
         }
         
         Self {
-            granules,
+            granules: Cells::new(granules),
             width,
             height,
-            is_floor: true,
+            edges: Edges::default(),
             right_sand: 0,
             left_sand: 0,
             unchanged: 0,
             unaccounted: 0,
             update_cycle: true,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            registry: ElementRegistry::shared_default(),
+            chunks: ChunkMap::new(width, height, Edges::default()),
+            level_search: LevelSearch::default(),
+            history: History::default(),
+            threads: 1,
         }
     }
 
+    pub fn registry(&self) -> &ElementRegistry {
+        &self.registry
+    }
+
+    // Swaps in a different set of element definitions. Granules already on
+    // the board keep the state they were created with.
+    pub fn set_registry(&mut self, registry: Arc<ElementRegistry>) {
+        self.registry = registry;
+        self.wake_all();
+    }
+
+    fn new_element(&mut self, element_type: ElementType) -> Element {
+        self.registry.create(element_type, &mut self.rng)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    // How many threads tick spreads the chunks over. The board comes out the
+    // same whatever this is set to, 1 keeps everything on the calling thread.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Restarts the random sequence from `seed`. Two boards with the same
+    // contents and the same seed tick identically from this point on.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Empties the board, as an edit of its own that can be undone.
     pub fn reset(&mut self) {
-        let indices = self.width * self.height;
-        let mut granules = Vec::new();
-        for i in 0..indices {
-            let element = Element::new(ElementType::Empty);
-            granules.push(element);
+        self.finish_edit();
+        let empty = Element::blank(ElementType::Empty);
+        if self.granules.iter().any(|granule| *granule != empty) {
+            self.history.record_reset(self.granules.as_slice().to_vec());
+        }
+        self.empty_board();
+        self.wake_all();
+    }
+
+    fn empty_board(&mut self) {
+        for index in 0..self.granules.len() {
+            self.granules[index] = Element::blank(ElementType::Empty);
         }
-        self.granules = granules;
     }
 
     #[allow(dead_code)]
@@ -62,10 +148,11 @@ impl SandBoard {
             for x in 0..self.width {
                 let index = self.get_index_from_coordinates(x, y);
                 if index < 10 {
-                    self.granules[index] = Element::new(ElementType::Sand);
+                    self.granules[index] = self.new_element(ElementType::Sand);
                 }
             }
         }
+        self.wake_all();
     }
 
     #[allow(dead_code)]
@@ -74,87 +161,105 @@ impl SandBoard {
             for x in 0..self.width {
                 let index = self.get_index_from_coordinates(x, y);
                 if y == self.height / 2 {
-                    self.granules[index] = Element::new(ElementType::Sand);
+                    self.granules[index] = self.new_element(ElementType::Sand);
                 }
             }
         }
+        self.wake_all();
     }
 
 
     #[allow(dead_code)]
     pub fn randomize(&mut self) {
-        let mut rng = rand::thread_rng();
         for y in 0..self.height {
             for x in 0..self.width {
                 let index = self.get_index_from_coordinates(x, y);
-                let rand_val: f64 = rng.gen();
+                let rand_val: f64 = self.rng.gen();
                 if rand_val > 0.5 {
-                    self.granules[index] = Element::new(ElementType::Sand);
+                    self.granules[index] = self.new_element(ElementType::Sand);
                 }
             }
         }
+        self.wake_all();
     }
 
     pub fn randomize_top(&mut self) {
-        let mut rng = rand::thread_rng();
         for y in 0..self.height {
             for x in 0..self.width {
                 if y < self.height / 2 {
                     let index = self.get_index_from_coordinates(x, y);
-                    let rand_val: f64 = rng.gen();
+                    let rand_val: f64 = self.rng.gen();
                     if rand_val > 0.5 {
-                        self.granules[index] = Element::new(ElementType::Sand);
+                        self.granules[index] = self.new_element(ElementType::Sand);
                     }
                     
                 }
             }
         }
+        self.wake_all();
     }
     pub fn randomize_ocean(&mut self) {
-        let mut rng = rand::thread_rng();
         for y in 0..self.height {
             for x in 0..self.width {
                 if y < ((self.height / 10) * 9) {
                     let index = self.get_index_from_coordinates(x, y);
-                    let rand_val: f64 = rng.gen();
+                    let rand_val: f64 = self.rng.gen();
                     if rand_val > 0.95 {
-                        self.granules[index] = Element::new(ElementType::Sand);
+                        self.granules[index] = self.new_element(ElementType::Sand);
                     }
                     else if rand_val > 0.15{
-                        self.granules[index] = Element::new(ElementType::Water);
+                        self.granules[index] = self.new_element(ElementType::Water);
                     }
                     
                 }
             }
         }
+        self.wake_all();
     }
 
     pub fn boring_ocean(&mut self) {
-        let mut rng = rand::thread_rng();
         for y in 0..self.height {
             for x in 0..self.width {
                 if y < ((self.height / 20) * 19) {
                     let index = self.get_index_from_coordinates(x, y);
-                    self.granules[index] = Element::new(ElementType::Water);
+                    self.granules[index] = self.new_element(ElementType::Water);
                 }
                 else {
                     let index = self.get_index_from_coordinates(x, y);
-                    self.granules[index] = Element::new(ElementType::Sand);
+                    self.granules[index] = self.new_element(ElementType::Sand);
                 }
             }
         }
+        self.wake_all();
+    }
+
+    // Fills the board using one of the scenario functions above, looked up
+    // by name. Returns false if there's no scenario with that name.
+    pub fn apply_scenario(&mut self, name: &str) -> bool {
+        match name {
+            "first_ten" => self.first_ten(),
+            "middle" => self.middle(),
+            "randomize" => self.randomize(),
+            "randomize_top" => self.randomize_top(),
+            "randomize_ocean" => self.randomize_ocean(),
+            "boring_ocean" => self.boring_ocean(),
+            "boring_sand" => self.boring_sand(),
+            "empty" => {},
+            _ => return false,
+        }
+        true
     }
 
     pub fn boring_sand(&mut self) {
-        let mut rng = rand::thread_rng();
         for y in 0..self.height {
             for x in 0..self.width {
                 if y > ((self.height / 20) * 19) {
                     let index = self.get_index_from_coordinates(x, y);
-                    self.granules[index] = Element::new(ElementType::Sand);
+                    self.granules[index] = self.new_element(ElementType::Sand);
                 }
             }
         }
+        self.wake_all();
     }
 
     fn get_index_from_coordinates(&self, x: usize, y: usize) -> usize {
@@ -168,68 +273,10 @@ impl SandBoard {
 
     pub fn output_texture(&self) -> ScreenTexture {
         let max_index = self.width * self.height;
-        let mut pixel_data: Vec<u8> = Vec::new();
+        let mut pixel_data: Vec<u8> = Vec::with_capacity(max_index * 4);
         for i in 0..max_index {
-            if self.granules[i].element_type == ElementType::Sand {
-                pixel_data.push(COLORS_YELLOW[0]);
-                pixel_data.push(COLORS_YELLOW[1]);
-                pixel_data.push(COLORS_YELLOW[2]);
-                pixel_data.push(COLORS_YELLOW[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Water {
-                pixel_data.push(COLORS_BLUE[0]);
-                pixel_data.push(COLORS_BLUE[1]);
-                pixel_data.push(COLORS_BLUE[2]);
-                pixel_data.push(COLORS_BLUE[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Wall {
-                pixel_data.push(COLORS_GREY[0]);
-                pixel_data.push(COLORS_GREY[1]);
-                pixel_data.push(COLORS_GREY[2]);
-                pixel_data.push(COLORS_GREY[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Dirt {
-                pixel_data.push(COLORS_BROWN[0]);
-                pixel_data.push(COLORS_BROWN[1]);
-                pixel_data.push(COLORS_BROWN[2]);
-                pixel_data.push(COLORS_BROWN[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Seed {
-                pixel_data.push(COLORS_LIGHT_GREEN[0]);
-                pixel_data.push(COLORS_LIGHT_GREEN[1]);
-                pixel_data.push(COLORS_LIGHT_GREEN[2]);
-                pixel_data.push(COLORS_LIGHT_GREEN[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Grass {
-                pixel_data.push(COLORS_PALE_YELLOW[0]);
-                pixel_data.push(COLORS_PALE_YELLOW[1]);
-                pixel_data.push(COLORS_PALE_YELLOW[2]);
-                pixel_data.push(COLORS_PALE_YELLOW[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Kelp {
-                pixel_data.push(COLORS_DARK_GREEN[0]);
-                pixel_data.push(COLORS_DARK_GREEN[1]);
-                pixel_data.push(COLORS_DARK_GREEN[2]);
-                pixel_data.push(COLORS_DARK_GREEN[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Minnow {
-                pixel_data.push(COLORS_DARK_RED[0]);
-                pixel_data.push(COLORS_DARK_RED[1]);
-                pixel_data.push(COLORS_DARK_RED[2]);
-                pixel_data.push(COLORS_DARK_RED[3]);
-            }
-            else if self.granules[i].element_type == ElementType::Egg {
-                pixel_data.push(COLORS_DARK_ORANGE[0]);
-                pixel_data.push(COLORS_DARK_ORANGE[1]);
-                pixel_data.push(COLORS_DARK_ORANGE[2]);
-                pixel_data.push(COLORS_DARK_ORANGE[3]);
-            }
-            else {
-                pixel_data.push(COLORS_MARIO_BACKGROUND[0]);
-                pixel_data.push(COLORS_MARIO_BACKGROUND[1]);
-                pixel_data.push(COLORS_MARIO_BACKGROUND[2]);
-                pixel_data.push(COLORS_MARIO_BACKGROUND[3]);
-            }
+            let color = self.registry.color(self.granules[i].element_type);
+            pixel_data.extend_from_slice(&color);
         }
 
         let dimensions = (self.width, self.height);
@@ -241,6 +288,12 @@ impl SandBoard {
 
     }
 
+    // The board as it'd be drawn, without the UI on top, scaled up by an
+    // integer factor. Needs no GPU, so it works from headless runs.
+    pub fn render_to_image(&self, scale: u32) -> image::ImageResult<image::RgbaImage> {
+        self.output_texture().to_image(scale)
+    }
+
     pub fn add_granules(&mut self, x: usize, y: usize, radius: usize, granule_type: ElementType) -> usize {
         let mut added_granules = 0;
         let rad: i64 = radius as i64;
@@ -251,14 +304,17 @@ impl SandBoard {
                 if self.is_in_coordinate_bounds_signed(new_x, new_y) {
                     if SandBoard::get_distance((new_x as f64, new_y as f64), (x as f64, y as f64)) < radius as f64 {
                         let index = self.get_index_from_coordinates(new_x as usize, new_y as usize);
-                        if self.granules[index].element_type == ElementType::Empty {
+                        let before = self.granules[index];
+                        if before.element_type == ElementType::Empty {
                             added_granules += 1;
                         }
                         self.add_granule(new_x as usize, new_y as usize, granule_type);
+                        self.history.record(index, before, self.granules[index]);
                     }
                 }    
             }
         }   
+        self.wake_region(x.saturating_sub(radius), y.saturating_sub(radius), x + radius, y + radius);
         return added_granules
     }
 
@@ -271,40 +327,23 @@ impl SandBoard {
     fn add_granule(&mut self, x: usize, y: usize, granule_type: ElementType) {
         if self.is_in_coordinate_bounds(x, y) {
             let index = self.get_index_from_coordinates(x, y);
-            self.granules[index] = Element::new(granule_type);
+            self.granules[index] = self.new_element(granule_type);
         }
     }
 
     fn update_granule(&mut self, x: usize, y: usize, granule_type: ElementType) {
         let index = self.get_index_from_coordinates(x, y);
-        self.granules[index] = Element::new(granule_type);
+        self.granules[index] = self.new_element(granule_type);
         self.granules[index].update_toggle = self.update_cycle;
     }
 
     pub fn tick(&mut self) {
-        let max_index = self.height * self.width;
-        let mut rng = rand::thread_rng();
-
         //let mut vec: Vec<usize> = (0..max_index).collect();
         //vec.shuffle(&mut rng);
 
-        for i in 0..max_index {
-            //let rand_index = vec[i];
-            
-
-            let mut index = i;
-            if (i / self.width) % 2 == 0 {
-                let x = i % self.width;
-                index =  i - x + (self.width - x - 1);
-            }
-
-            if self.granules[index].element_type == ElementType::Empty {
-                continue;
-            }
-
-            let surrounding_granule = self.get_surrounding_from_index(index);
-            self.granule_tick_new(index);
-        }
+        self.feed_sources();
+        self.evaporate();
+        self.tick_chunks();
         if self.update_cycle {
             self.update_cycle = false;
         }
@@ -319,40 +358,34 @@ impl SandBoard {
             let center_index = self.get_index_from_coordinates(x, y);
             return self.get_surrounding_from_index(center_index)
         }
-        return [[Element::new(ElementType::Empty); 3]; 3]
+        return [[Element::blank(ElementType::Empty); 3]; 3]
     }
 
 
     fn get_surrounding_from_index(&self, center_index: usize) -> [[Element; 3]; 3] {
-        let mut grid = [[Element::new(ElementType::Empty); 3]; 3];
+        let mut grid = [[Element::blank(ElementType::Empty); 3]; 3];
         let (center_x, center_y) = self.get_coordinates_from_index(center_index);
-        let x_start: i64 = center_x as i64 - 1;
-        let y_start: i64 = center_y as i64 - 1;
-        for k in 0..=2 {
-            if y_start == -1 && k == 0 {
-                grid[k] = [Element::new(ElementType::ScreenEdge); 3];
-                continue
+        if center_x > 0 && center_x + 1 < self.width && center_y > 0 && center_y + 1 < self.height {
+            for (k, row) in grid.iter_mut().enumerate() {
+                let row_start = self.get_index_from_coordinates(center_x - 1, center_y + k - 1);
+                row.copy_from_slice(&self.granules[row_start..row_start + 3]);
             }
-            else if center_y == (self.height - 1) && k == 2{
-                grid[k] = [Element::new(ElementType::ScreenEdge); 3];
-                continue
-            }
-            for i in 0..=2 {
-                if x_start == -1 && i == 0 {
-                    grid[k][i] = Element::new(ElementType::ScreenEdge);
-                    continue
-                }
-                else if center_x == (self.width - 1) && i == 2 {
-                    grid[k][i] = Element::new(ElementType::ScreenEdge);
-                    continue
-                }
-                let x = (x_start + i as i64) as usize;
-                let y = (y_start + k as i64) as usize;
-                let index = self.get_index_from_coordinates(x, y);
-                grid[k][i] = self.granules[index];
+            return grid
+        }
+        for (k, row) in grid.iter_mut().enumerate() {
+            for (i, cell) in row.iter_mut().enumerate() {
+                let x = center_x as i64 + i as i64 - 1;
+                let y = center_y as i64 + k as i64 - 1;
+                *cell = match self.resolve_coordinates(x, y) {
+                    Ok((x, y)) => self.granules[self.get_index_from_coordinates(x, y)],
+                    // there's nothing past a void edge, and every other edge
+                    // is as good as a wall
+                    Err(EdgeMode::Void) => Element::blank(ElementType::Empty),
+                    Err(_) => Element::blank(ElementType::ScreenEdge),
+                };
             }
         }
-        return grid
+        grid
     }
 
     fn is_in_coordinate_bounds(&self, x: usize, y: usize) -> bool {
@@ -369,6 +402,19 @@ impl SandBoard {
         return false
     }
 
+    // Number of cells of each element type on the board, in the order each
+    // type is first seen scanning from the top left.
+    pub fn get_element_counts(&self) -> Vec<(ElementType, usize)> {
+        let mut counts: Vec<(ElementType, usize)> = Vec::new();
+        for granule in self.granules.iter() {
+            match counts.iter_mut().find(|(element_type, _)| *element_type == granule.element_type) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((granule.element_type, 1)),
+            }
+        }
+        counts
+    }
+
     pub fn get_granule_count(&self) -> usize {
         let mut count = 0;
         for i in 0..self.granules.len() {
@@ -383,6 +429,8 @@ impl SandBoard {
 
 
 
+pub const SCENARIO_NAMES: [&str; 8] = ["empty", "first_ten", "middle", "randomize", "randomize_top", "randomize_ocean", "boring_ocean", "boring_sand"];
+
 pub const COLORS_YELLOW: [u8; 4] = [0xCC, 0xCC, 0x00, 0xFF];
 pub const COLORS_BLUE: [u8; 4] = [0x00, 0xCC, 0xFF, 0xFF];
 pub const COLORS_MARIO_BACKGROUND: [u8; 4] = [0x09, 0x26, 0x41, 0xFF];
@@ -396,11 +444,27 @@ pub const COLORS_PALE_YELLOW: [u8; 4] = [0xFF, 0xFC, 0xD3, 0xFF];
 pub const COLORS_DARK_GREEN: [u8; 4] = [0x17, 0x35, 0x18, 0xFF];
 pub const COLORS_DARK_RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
 pub const COLORS_DARK_ORANGE: [u8; 4] = [0xE7, 0x96, 0x8B, 0xFF];
+pub const COLORS_ORANGE: [u8; 4] = [0xFF, 0x6A, 0x00, 0xFF];
+pub const COLORS_DARK_GREY: [u8; 4] = [0x4A, 0x4A, 0x4A, 0xFF];
+pub const COLORS_WARM_GREY: [u8; 4] = [0x7E, 0x78, 0x70, 0xFF];
+pub const COLORS_MOSS_GREEN: [u8; 4] = [0x4C, 0x7A, 0x2C, 0xFF];
+pub const COLORS_DARK_BROWN: [u8; 4] = [0x3B, 0x2A, 0x1A, 0xFF];
+pub const COLORS_FROG_GREEN: [u8; 4] = [0x5D, 0xB0, 0x3A, 0xFF];
+pub const COLORS_SLATE: [u8; 4] = [0x6E, 0x6A, 0x80, 0xFF];
+pub const COLORS_OLIVE_BROWN: [u8; 4] = [0x6B, 0x55, 0x2E, 0xFF];
+pub const COLORS_TAN: [u8; 4] = [0xC2, 0x8E, 0x5A, 0xFF];
+pub const COLORS_LILAC: [u8; 4] = [0xC8, 0xA8, 0xE8, 0xFF];
+pub const COLORS_ICE_BLUE: [u8; 4] = [0xA8, 0xDC, 0xF0, 0xFF];
+pub const COLORS_STEAM_GREY: [u8; 4] = [0xC4, 0xCC, 0xD4, 0xFF];
+pub const COLORS_SNOW_WHITE: [u8; 4] = [0xEE, 0xF4, 0xFF, 0xFF];
+pub const COLORS_TEAL: [u8; 4] = [0x1F, 0x9E, 0x8F, 0xFF];
+pub const COLORS_CHARCOAL: [u8; 4] = [0x24, 0x1E, 0x26, 0xFF];
 
 
 #[cfg(test)]
 mod sand_test {
     use super::*; 
+    use super::update_functions::{MINNOW_BROOD_MEALS, TADPOLE_MEALS};
 
     #[test]
     fn coordinate_test() {
@@ -416,4 +480,469 @@ mod sand_test {
         }
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn seeded_boards_tick_identically() {
+        let mut first = SandBoard::with_seed(60, 60, 1234);
+        let mut second = SandBoard::with_seed(60, 60, 1234);
+        for board in [&mut first, &mut second] {
+            board.randomize_ocean();
+            board.add_granules(20, 10, 4, ElementType::Egg);
+            board.add_granules(40, 10, 1, ElementType::Seed);
+            for _ in 0..200 {
+                board.tick();
+            }
+        }
+        assert!(first.granules == second.granules);
+    }
+
+    #[test]
+    fn fire_burns_dry_fuel_and_water_puts_it_out() {
+        let mut sand_board = SandBoard::with_seed(40, 30, 5);
+        // grass standing in the open, kelp under water, and a fire on the water
+        for x in 2..18 {
+            sand_board.add_granule(x, 10, ElementType::Grass);
+        }
+        for y in 20..30 {
+            for x in 20..40 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        for y in 25..30 {
+            sand_board.add_granule(30, y, ElementType::Kelp);
+        }
+        sand_board.add_granule(2, 9, ElementType::Fire);
+        sand_board.add_granule(30, 19, ElementType::Fire);
+        sand_board.wake_all();
+
+        for _ in 0..1500 {
+            sand_board.tick();
+        }
+        let counts = sand_board.get_element_counts();
+        let count = |element_type| counts.iter().find(|(counted, _)| *counted == element_type).map_or(0, |(_, count)| *count);
+        assert_eq!(count(ElementType::Grass), 0);
+        assert_eq!(count(ElementType::Kelp), 5);
+        assert_eq!(count(ElementType::Fire), 0);
+        assert!(count(ElementType::Ash) > 0);
+    }
+
+    #[test]
+    fn moss_creeps_along_surfaces_near_water() {
+        let mut sand_board = SandBoard::with_seed(64, 32, 12);
+        // a pool held in by a low wall, on a wall floor
+        for x in 0..64 {
+            sand_board.add_granule(x, 20, ElementType::Wall);
+        }
+        for y in 15..20 {
+            sand_board.add_granule(6, y, ElementType::Wall);
+        }
+        for y in 17..20 {
+            for x in 0..6 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.add_granule(5, 16, ElementType::Moss);
+        // and some moss with nothing to hold on to
+        sand_board.add_granule(40, 5, ElementType::Moss);
+        sand_board.wake_all();
+
+        for _ in 0..1500 {
+            sand_board.tick();
+        }
+        let moss_at = |x, y| sand_board.granules[sand_board.get_index_from_coordinates(x, y)].element_type == ElementType::Moss;
+        assert!((7..64).filter(|x| moss_at(*x, 19)).count() > 5);
+        for y in 0..18 {
+            for x in 8..64 {
+                assert!(!moss_at(x, y), "moss in open space at {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn frog_life_cycle() {
+        let mut sand_board = SandBoard::with_seed(32, 32, 4);
+        for y in 26..32 {
+            for x in 0..32 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.add_granule(10, 31, ElementType::Egg);
+        // a tadpole one meal away from turning into a frog
+        sand_board.add_granule(25, 29, ElementType::Tadpole);
+        sand_board.add_granule(26, 29, ElementType::Kelp);
+        let tadpole_index = sand_board.get_index_from_coordinates(25, 29);
+        sand_board.granules[tadpole_index].growth = TADPOLE_MEALS - 1;
+        // and a thirsty frog walled in with no water in sight
+        for y in 0..10 {
+            for x in 0..10 {
+                sand_board.add_granule(x, y, ElementType::Wall);
+            }
+        }
+        sand_board.add_granule(5, 4, ElementType::Frog);
+        let frog_index = sand_board.get_index_from_coordinates(5, 4);
+        sand_board.granules[frog_index].moisture = 3;
+        sand_board.wake_all();
+
+        for _ in 0..300 {
+            sand_board.tick();
+        }
+        let element_at = |x, y| sand_board.granules[sand_board.get_index_from_coordinates(x, y)].element_type;
+        // the egg in shallow water hatched, and the other tadpole grew up
+        assert!(sand_board.granules.iter().filter(|granule| granule.element_type == ElementType::Tadpole).count() == 1);
+        assert!(sand_board.granules.iter().filter(|granule| granule.element_type == ElementType::Frog).count() == 1);
+        assert_eq!(element_at(26, 29), ElementType::Water);
+        assert_eq!(element_at(5, 4), ElementType::Empty);
+    }
+
+    #[test]
+    fn tadpoles_loaded_fully_grown_still_eat() {
+        let mut sand_board = SandBoard::with_seed(8, 8, 2);
+        for y in 4..8 {
+            for x in 0..8 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.add_granule(3, 6, ElementType::Tadpole);
+        sand_board.add_granule(4, 6, ElementType::Kelp);
+        let tadpole_index = sand_board.get_index_from_coordinates(3, 6);
+        sand_board.granules[tadpole_index].growth = u8::MAX;
+        sand_board.tick();
+        assert_eq!(sand_board.granules[tadpole_index].element_type, ElementType::Frog);
+    }
+
+    #[test]
+    fn frogs_dont_hop_over_thin_walls() {
+        let mut sand_board = SandBoard::with_seed(24, 16, 8);
+        // a thirsty frog on dry ground, and a tank of water behind a wall
+        // two cells thick and too high to hop over
+        for x in 0..24 {
+            sand_board.add_granule(x, 15, ElementType::Wall);
+        }
+        for y in 8..15 {
+            for x in 8..10 {
+                sand_board.add_granule(x, y, ElementType::Wall);
+            }
+        }
+        for y in 12..15 {
+            for x in 10..24 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.add_granule(4, 14, ElementType::Frog);
+        let frog_index = sand_board.get_index_from_coordinates(4, 14);
+        sand_board.granules[frog_index].moisture = 200;
+        sand_board.wake_all();
+
+        let frog_x = |sand_board: &SandBoard| sand_board.granules.iter().position(|granule| granule.element_type == ElementType::Frog)
+            .map(|index| sand_board.get_coordinates_from_index(index).0);
+        let mut furthest = 0;
+        for _ in 0..300 {
+            sand_board.tick();
+            let Some(x) = frog_x(&sand_board) else {
+                break
+            };
+            furthest = furthest.max(x);
+        }
+        assert!(furthest > 4);
+        assert!(furthest < 8, "frog got over the wall to {}", furthest);
+    }
+
+    #[test]
+    fn isopods_find_food_and_breed() {
+        let mut sand_board = SandBoard::with_seed(48, 16, 9);
+        for x in 0..48 {
+            sand_board.add_granule(x, 15, ElementType::Dirt);
+        }
+        // a step to climb on the way to a heap of detritus
+        sand_board.add_granule(12, 14, ElementType::Dirt);
+        for x in 30..36 {
+            sand_board.add_granule(x, 14, ElementType::Detritus);
+        }
+        sand_board.add_granule(4, 14, ElementType::Isopod);
+        let isopod_index = sand_board.get_index_from_coordinates(4, 14);
+        sand_board.granules[isopod_index].hunger = 200;
+        sand_board.wake_all();
+
+        for _ in 0..1500 {
+            sand_board.tick();
+        }
+        let count = |element_type| sand_board.granules.iter().filter(|granule| granule.element_type == element_type).count();
+        assert!(count(ElementType::Detritus) < 6);
+        // eggs laid on land hatch into more isopods
+        assert!(count(ElementType::Isopod) + count(ElementType::Egg) > 1);
+    }
+
+    #[test]
+    fn falling_granules_speed_up_and_stop_at_walls() {
+        let mut sand_board = SandBoard::with_seed(8, 100, 1);
+        // a wall one cell thick that a fast granule mustn't skip over
+        for x in 0..8 {
+            sand_board.add_granule(x, 80, ElementType::Wall);
+        }
+        sand_board.add_granule(4, 0, ElementType::Sand);
+        let height_of_sand = |sand_board: &SandBoard| (0..100).find(|y| sand_board.granules[sand_board.get_index_from_coordinates(4, *y)].element_type == ElementType::Sand);
+
+        let mut ticks = 0;
+        while height_of_sand(&sand_board) != Some(79) {
+            sand_board.tick();
+            ticks += 1;
+            assert!(ticks < 79, "sand fell no faster than a cell a tick");
+        }
+        for _ in 0..5 {
+            sand_board.tick();
+        }
+        assert_eq!(height_of_sand(&sand_board), Some(79));
+        assert_eq!(sand_board.granules[sand_board.get_index_from_coordinates(4, 79)].velocity_y, 0);
+        assert_eq!(sand_board.granules[sand_board.get_index_from_coordinates(4, 80)].element_type, ElementType::Wall);
+    }
+
+    #[test]
+    fn landing_water_splashes_sideways() {
+        let mut sand_board = SandBoard::with_seed(64, 64, 1);
+        sand_board.add_granule(32, 0, ElementType::Water);
+        let position_of_water = |sand_board: &SandBoard| sand_board.granules.iter()
+            .position(|granule| granule.element_type == ElementType::Water)
+            .map(|index| sand_board.get_coordinates_from_index(index))
+            .unwrap();
+
+        let mut furthest_slide = 0;
+        let mut last_x = 32;
+        for _ in 0..60 {
+            sand_board.tick();
+            let (x, _) = position_of_water(&sand_board);
+            furthest_slide = furthest_slide.max(x.abs_diff(last_x));
+            last_x = x;
+        }
+        assert_eq!(position_of_water(&sand_board).1, 63);
+        assert!(furthest_slide > 1);
+    }
+
+    #[test]
+    fn liquids_and_gases_settle_by_density() {
+        let registry = ElementRegistry::parse(
+            "[oil]\ncolor = #3A2A10\nbehaviour = liquid\ndensity = 80\n\
+             [brine]\ncolor = #9FC7D8\nbehaviour = liquid\ndensity = 120\n\
+             [vapour]\ncolor = #D0D8E0\nbehaviour = gas\ndensity = 1"
+        ).unwrap();
+        let mut sand_board = SandBoard::with_seed(4, 24, 3);
+        sand_board.set_registry(Arc::new(registry));
+        // stacked upside down, lightest at the bottom
+        let layers = [ElementType::Sand, ElementType::Custom(1), ElementType::Water, ElementType::Custom(0), ElementType::Custom(2)];
+        for (layer, element_type) in layers.into_iter().enumerate() {
+            for y in 4 + layer * 4..8 + layer * 4 {
+                for x in 0..4 {
+                    sand_board.add_granule(x, y, element_type);
+                }
+            }
+        }
+        sand_board.wake_all();
+
+        for _ in 0..300 {
+            sand_board.tick();
+        }
+        for x in 0..4 {
+            let densities: Vec<u8> = (0..24).rev()
+                .map(|y| sand_board.granules[sand_board.get_index_from_coordinates(x, y)].element_type)
+                .filter(|element_type| *element_type != ElementType::Empty)
+                .map(|element_type| sand_board.registry.density(element_type))
+                .collect();
+            assert!(densities.windows(2).all(|pair| pair[0] >= pair[1]), "column {}: {:?}", x, densities);
+        }
+    }
+
+    #[test]
+    fn water_levels_out_across_a_divider() {
+        // a wall down the middle with a gap under it, and the water poured
+        // in on the left
+        let mut sand_board = SandBoard::with_seed(64, 48, 4);
+        for y in 0..44 {
+            sand_board.add_granule(32, y, ElementType::Wall);
+        }
+        for y in 20..48 {
+            for x in 0..32 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        for y in 44..48 {
+            for x in 32..64 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.wake_all();
+
+        for _ in 0..2000 {
+            sand_board.tick();
+        }
+        let surface = |x: usize| (0..48)
+            .find(|y| sand_board.granules[sand_board.get_index_from_coordinates(x, *y)].element_type == ElementType::Water)
+            .unwrap();
+        let (left, right) = (surface(16), surface(48));
+        assert!(left.abs_diff(right) <= 2, "left surface at {}, right at {}", left, right);
+        assert!(right < 40, "right surface at {}", right);
+    }
+
+    #[test]
+    fn seeds_only_sprout_on_damp_soil() {
+        // a sand bed with a pond at one end
+        let mut sand_board = SandBoard::with_seed(48, 16, 2);
+        for x in 0..48 {
+            sand_board.add_granule(x, 15, ElementType::Wall);
+            for y in 12..15 {
+                sand_board.add_granule(x, y, ElementType::Sand);
+            }
+        }
+        for y in 9..12 {
+            sand_board.add_granule(8, y, ElementType::Wall);
+            for x in 0..8 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.add_granule(11, 11, ElementType::Seed);
+        sand_board.add_granule(40, 11, ElementType::Seed);
+        sand_board.wake_all();
+
+        for _ in 0..100 {
+            sand_board.tick();
+        }
+        let element_at = |x, y| sand_board.granules[sand_board.get_index_from_coordinates(x, y)].element_type;
+        let moisture_at = |x, y| sand_board.granules[sand_board.get_index_from_coordinates(x, y)].moisture;
+        assert_eq!(element_at(11, 11), ElementType::Grass);
+        assert_eq!(element_at(40, 11), ElementType::Seed);
+        // soaked under the pond, drying out further away
+        assert_eq!(moisture_at(4, 12), u8::MAX);
+        assert!(moisture_at(12, 12) > moisture_at(16, 12));
+        assert_eq!(moisture_at(40, 12), 0);
+    }
+
+    #[test]
+    fn minnows_feed_breed_and_starve() {
+        // two deep tanks, one with a kelp bed and one without
+        let mut sand_board = SandBoard::with_seed(64, 40, 3);
+        for y in 8..40 {
+            sand_board.add_granule(32, y, ElementType::Wall);
+            for x in (0..64).filter(|x| *x != 32) {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        for x in 4..12 {
+            sand_board.add_granule(x, 39, ElementType::Kelp);
+        }
+        // a hungry minnow one meal away from laying, and a starving one
+        for (x, hunger) in [(20, 150), (48, 254)] {
+            sand_board.add_granule(x, 20, ElementType::Minnow);
+            let minnow_index = sand_board.get_index_from_coordinates(x, 20);
+            sand_board.granules[minnow_index].hunger = hunger;
+            sand_board.granules[minnow_index].growth = MINNOW_BROOD_MEALS - 1;
+        }
+        sand_board.wake_all();
+
+        for _ in 0..800 {
+            sand_board.tick();
+        }
+        let count = |element_type, columns: std::ops::Range<usize>| (0..40)
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .filter(|(x, y)| sand_board.granules[sand_board.get_index_from_coordinates(*x, *y)].element_type == element_type)
+            .count();
+        assert!(count(ElementType::Minnow, 0..32) + count(ElementType::Egg, 0..32) > 1);
+        assert_eq!(count(ElementType::Minnow, 33..64), 0);
+        assert_eq!(count(ElementType::Detritus, 33..64), 1);
+    }
+
+    #[test]
+    fn snails_graze_and_starve_without_food() {
+        // two ponds, one with kelp in it and one without
+        let mut sand_board = SandBoard::with_seed(48, 16, 5);
+        for y in 6..16 {
+            sand_board.add_granule(24, y, ElementType::Wall);
+            for x in (0..48).filter(|x| *x != 24) {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        for y in 12..16 {
+            sand_board.add_granule(6, y, ElementType::Kelp);
+        }
+        for x in [10, 34] {
+            sand_board.add_granule(x, 15, ElementType::Snail);
+            let snail_index = sand_board.get_index_from_coordinates(x, 15);
+            sand_board.granules[snail_index].hunger = 240;
+        }
+        sand_board.wake_all();
+
+        for _ in 0..3000 {
+            sand_board.tick();
+        }
+        let snails = |columns: std::ops::Range<usize>| (0..16)
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .filter(|(x, y)| sand_board.granules[sand_board.get_index_from_coordinates(*x, *y)].element_type == ElementType::Snail)
+            .count();
+        assert!(snails(0..24) > 0);
+        assert_eq!(snails(25..48), 0);
+    }
+
+    #[test]
+    fn springtails_need_damp_ground() {
+        let mut sand_board = SandBoard::with_seed(48, 16, 6);
+        // dirt kept damp by puddles on the left, dry sand on the right
+        for x in 0..48 {
+            sand_board.add_granule(x, 15, ElementType::Wall);
+            let ground = match x {
+                0..=23 if x % 4 == 0 => ElementType::Water,
+                0..=23 => ElementType::Dirt,
+                _ => ElementType::Sand,
+            };
+            sand_board.add_granule(x, 14, ground);
+        }
+        for y in 0..14 {
+            sand_board.add_granule(24, y, ElementType::Wall);
+        }
+        for x in (4..20).filter(|x| x % 4 != 0) {
+            sand_board.add_granule(x, 13, ElementType::Detritus);
+        }
+        sand_board.add_granule(12, 12, ElementType::SpringTail);
+        sand_board.add_granule(36, 13, ElementType::SpringTail);
+        sand_board.wake_all();
+
+        let count = |sand_board: &SandBoard, element_type| sand_board.granules.iter().filter(|granule| granule.element_type == element_type).count();
+        let mut most_springtails = 0;
+        for _ in 0..1000 {
+            sand_board.tick();
+            most_springtails = most_springtails.max(count(&sand_board, ElementType::SpringTail));
+        }
+        assert!(most_springtails > 2);
+        assert!(count(&sand_board, ElementType::Detritus) < 12);
+        let dry_side = (0..16).flat_map(|y| (25..48).map(move |x| (x, y)))
+            .filter(|(x, y)| sand_board.granules[sand_board.get_index_from_coordinates(*x, *y)].element_type == ElementType::SpringTail)
+            .count();
+        assert_eq!(dry_side, 0);
+    }
+
+    #[test]
+    fn springtails_dont_jump_through_walls() {
+        let mut sand_board = SandBoard::with_seed(32, 32, 7);
+        // a sealed box one cell thick, with damp dirt inside to live on
+        for k in 10..=17 {
+            for (x, y) in [(k, 10), (k, 17), (10, k), (17, k)] {
+                sand_board.add_granule(x, y, ElementType::Wall);
+            }
+        }
+        for x in 11..17 {
+            let ground = if x == 13 { ElementType::Water } else { ElementType::Dirt };
+            sand_board.add_granule(x, 16, ground);
+        }
+        sand_board.add_granule(14, 15, ElementType::SpringTail);
+        sand_board.wake_all();
+
+        let mut jumped = false;
+        for _ in 0..500 {
+            sand_board.tick();
+            jumped |= sand_board.granules[sand_board.get_index_from_coordinates(14, 15)].element_type != ElementType::SpringTail;
+            for (index, granule) in sand_board.granules.iter().enumerate() {
+                if granule.element_type == ElementType::SpringTail {
+                    let (x, y) = sand_board.get_coordinates_from_index(index);
+                    assert!((11..17).contains(&x) && (11..17).contains(&y), "springtail got out to {}, {}", x, y);
+                }
+            }
+        }
+        assert!(jumped);
+    }
+}
@@ -0,0 +1,146 @@
+// Water levels. A liquid only spreads a few cells along its own row, so on
+// its own the water in a U-tube, or either side of a wall with a gap under
+// it, stays at whatever level it was poured to. So after the chunks are
+// updated, the top of the liquid in each awake chunk looks through the liquid
+// it's connected to for a surface further down, and if it finds one the top
+// granule moves there. A granule at a time, that pushes the low side up until
+// both sides are level.
+//
+// Only liquid that's standing still counts, so a stream pouring into a pool
+// isn't mistaken for part of it. Each search gives up after SEARCH_LIMIT
+// cells, so very big vessels only level out with the ones nearby. Searching
+// is slow next to everything else a tick does, so the chunks take turns, one
+// in SEARCH_TURNS each tick, except for the ones that found somewhere to move
+// water to last tick, which keep going until they're level.
+
+use std::collections::VecDeque;
+
+use super::SandBoard;
+use super::elements::State;
+
+const SEARCH_LIMIT: usize = 2048;
+// how much lower a surface has to be to take water from the top, anything
+// less is left to the liquid's own spreading
+const LEVEL_DIFFERENCE: usize = 2;
+const SEARCH_TURNS: usize = 4;
+
+
+// Scratch space for the searches, kept between ticks so it isn't reallocated.
+// Cells marked with the current search's mark have been seen by it.
+#[derive(Default)]
+pub(crate) struct LevelSearch {
+    marks: Vec<u32>,
+    mark: u32,
+    queue: VecDeque<usize>,
+    turn: usize,
+    // chunks that moved water last tick
+    levelling: Vec<usize>,
+}
+
+impl SandBoard {
+    // Moves at most one granule of liquid in each of the chunks down to a
+    // lower surface it's connected to. Returns the cells that changed.
+    pub(super) fn equalize_levels(&mut self, chunks: &[usize]) -> Vec<usize> {
+        let mut search = std::mem::take(&mut self.level_search);
+        if search.marks.len() != self.granules.len() {
+            search.marks = vec![0; self.granules.len()];
+            search.mark = 0;
+        }
+
+        search.turn = (search.turn + 1) % SEARCH_TURNS;
+
+        let mut changed = Vec::new();
+        let mut levelling = Vec::new();
+        for chunk in chunks {
+            if chunk % SEARCH_TURNS != search.turn && !search.levelling.contains(chunk) {
+                continue
+            }
+            let Some(top_index) = self.liquid_top(*chunk) else {
+                continue
+            };
+            if let Some(space_index) = self.lower_surface(top_index, &mut search) {
+                self.granules.swap(top_index, space_index);
+                changed.push(top_index);
+                changed.push(space_index);
+                levelling.push(*chunk);
+            }
+        }
+        search.levelling = levelling;
+        self.level_search = search;
+        changed
+    }
+
+    // The highest liquid surface in the chunk, if there's any.
+    fn liquid_top(&self, chunk: usize) -> Option<usize> {
+        let (x_start, y_start, x_end, y_end) = self.chunk_bounds(chunk);
+        (y_start..y_end)
+            .flat_map(|y| (x_start..x_end).map(move |x| (x, y)))
+            .map(|(x, y)| self.get_index_from_coordinates(x, y))
+            .find(|index| self.space_above_surface(*index).is_some())
+    }
+
+    // Searches the liquid connected to the surface at `top_index` for another
+    // surface at least LEVEL_DIFFERENCE lower, and returns the free cell
+    // above it.
+    fn lower_surface(&self, top_index: usize, search: &mut LevelSearch) -> Option<usize> {
+        search.mark = search.mark.wrapping_add(1);
+        if search.mark == 0 {
+            search.marks.fill(0);
+            search.mark = 1;
+        }
+        search.queue.clear();
+        search.queue.push_back(top_index);
+        search.marks[top_index] = search.mark;
+
+        let element_type = self.granules[top_index].element_type;
+        let (_, top_y) = self.get_coordinates_from_index(top_index);
+        let mut searched = 0;
+        while let Some(index) = search.queue.pop_front() {
+            searched += 1;
+            if searched > SEARCH_LIMIT {
+                break
+            }
+            let (x, y) = self.get_coordinates_from_index(index);
+            if y >= top_y + LEVEL_DIFFERENCE {
+                if let Some(space_index) = self.space_above_surface(index) {
+                    return Some(space_index)
+                }
+            }
+            for (x_relative, y_relative) in [(0, 1), (-1, 0), (1, 0), (0, -1)] {
+                let (new_x, new_y) = (x as i64 + x_relative, y as i64 + y_relative);
+                if !self.is_in_coordinate_bounds_signed(new_x, new_y) {
+                    continue
+                }
+                let new_index = self.get_index_from_coordinates(new_x as usize, new_y as usize);
+                if search.marks[new_index] != search.mark && self.granules[new_index].element_type == element_type && self.is_resting_liquid(new_index) {
+                    search.marks[new_index] = search.mark;
+                    search.queue.push_back(new_index);
+                }
+            }
+        }
+        None
+    }
+
+    // The cell above a liquid surface, if the liquid there is standing still
+    // and has gas or empty space over it it could move into.
+    fn space_above_surface(&self, index: usize) -> Option<usize> {
+        if !self.is_resting_liquid(index) {
+            return None
+        }
+        let (x, y) = self.get_coordinates_from_index(index);
+        if y == 0 {
+            return None
+        }
+        let above_index = self.get_index_from_coordinates(x, y - 1);
+        if self.granules[above_index].state == State::Gas && self.can_sink_into(index, above_index) {
+            Some(above_index)
+        }
+        else {
+            None
+        }
+    }
+
+    fn is_resting_liquid(&self, index: usize) -> bool {
+        self.granules[index].state == State::Liquid && self.granules[index].velocity_y == 0
+    }
+}
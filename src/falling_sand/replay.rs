@@ -0,0 +1,476 @@
+// Recording and replaying sessions. A recording is the board as it was when
+// recording started, followed by everything done to it since (each Action,
+// stamped with how many ticks had passed when it happened). A board's run
+// depends only on its contents, its seed and what's done to it, so loading
+// the board and doing the same things at the same ticks plays the session
+// back exactly. The game makes every change to the board through an Action
+// so nothing it does is left out of a recording.
+//
+// A fingerprint of the board is kept after every tick, and a replay that
+// comes out different at any tick stops with ReplayError::Diverged, so a
+// recording attached to a bug report shows whether a fix changed anything.
+// Custom elements aren't in the recording, it has to be replayed with the
+// same definitions it was recorded with.
+//
+// File layout (all integers little endian):
+//
+//   magic         8 bytes   "SANDREC\0"
+//   version       u16       REPLAY_VERSION
+//   board         u32 length, then a save file (see save_file.rs)
+//   actions       u32 count, then for each:
+//       tick      u64       how many ticks had passed
+//       kind      u8        then what that kind of action needs:
+//                           0 paint: x, y, size u32, element, emits u8
+//                           1 finish edit
+//                           2 set edges: 4 * 2 u8 as in a save file
+//                           3 reset, 4 undo, 5 redo
+//                           6 load: u32 length, then a save file
+//   fingerprints  u64 count, then a u64 for each tick
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use super::SandBoard;
+use super::edges::{EdgeMode, Edges};
+use super::elements::ElementType;
+use super::element_registry::ElementRegistry;
+use super::save_file::SaveError;
+
+pub const REPLAY_MAGIC: [u8; 8] = *b"SANDREC\0";
+pub const REPLAY_VERSION: u16 = 1;
+
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Action {
+    // add_granules, or add_emitters putting out `emits` if the element is
+    // an emitter
+    Paint { x: usize, y: usize, size: usize, element: ElementType, emits: ElementType },
+    FinishEdit,
+    SetEdges(Edges),
+    Reset,
+    Undo,
+    Redo,
+    // a save file loaded over the board
+    Load(Vec<u8>),
+}
+
+impl Action {
+    pub fn apply(&self, sand_board: &mut SandBoard) -> Result<(), SaveError> {
+        match self {
+            Action::Paint { x, y, size, element: ElementType::Emitter, emits } => {
+                sand_board.add_emitters(*x, *y, *size, *emits);
+            },
+            Action::Paint { x, y, size, element, .. } => {
+                sand_board.add_granules(*x, *y, *size, *element);
+            },
+            Action::FinishEdit => sand_board.finish_edit(),
+            Action::SetEdges(edges) => sand_board.set_edges(*edges)?,
+            Action::Reset => sand_board.reset(),
+            Action::Undo => {
+                sand_board.undo();
+            },
+            Action::Redo => {
+                sand_board.redo();
+            },
+            Action::Load(bytes) => sand_board.load_into(bytes.as_slice())?,
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Save(SaveError),
+    BadMagic,
+    UnsupportedVersion(u16),
+    BadAction { index: usize },
+    // the board came out different to the recording after this many ticks
+    Diverged { tick: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Save(error) => write!(f, "{}", error),
+            ReplayError::BadMagic => write!(f, "not a sand recording file"),
+            ReplayError::UnsupportedVersion(version) => {
+                write!(f, "unsupported recording version {} (newest supported is {})", version, REPLAY_VERSION)
+            },
+            ReplayError::BadAction { index } => write!(f, "action {} is invalid", index),
+            ReplayError::Diverged { tick } => {
+                write!(f, "the board stopped matching the recording at tick {}", tick)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayError::Save(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SaveError> for ReplayError {
+    fn from(error: SaveError) -> Self {
+        ReplayError::Save(error)
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        ReplayError::Save(SaveError::from(error))
+    }
+}
+
+
+pub struct Recording {
+    // the board as recording started, as a save file
+    board: Vec<u8>,
+    actions: Vec<(usize, Action)>,
+    fingerprints: Vec<u64>,
+}
+
+impl Recording {
+    // Starts recording the board. It's restarted the way loading would
+    // restart it (its random sequence from the seed, and every chunk awake),
+    // so it carries on from exactly where a replay will start. Its history is
+    // kept, but held (see hold_history) until stop, since a replay has no
+    // edits from before it to undo.
+    pub fn start(sand_board: &mut SandBoard) -> Result<Recording, SaveError> {
+        let mut board = Vec::new();
+        sand_board.save(&mut board)?;
+        sand_board.restart();
+        sand_board.hold_history();
+        Ok(Recording { board, actions: Vec::new(), fingerprints: Vec::new() })
+    }
+
+    // Stops recording, letting undo reach the edits from before it again.
+    pub fn stop(&self, sand_board: &mut SandBoard) {
+        sand_board.release_history();
+    }
+
+    // Records an action and does it to the board.
+    pub fn act(&mut self, sand_board: &mut SandBoard, action: Action) -> Result<(), SaveError> {
+        action.apply(sand_board)?;
+        // finishing an edit only does anything right after painting
+        let is_after_paint = matches!(self.actions.last(), Some((_, Action::Paint { .. })));
+        if action != Action::FinishEdit || is_after_paint {
+            self.actions.push((self.ticks(), action));
+        }
+        Ok(())
+    }
+
+    // Ticks the board and records that it did.
+    pub fn tick(&mut self, sand_board: &mut SandBoard) {
+        sand_board.tick();
+        self.fingerprints.push(sand_board.fingerprint());
+    }
+
+    pub fn ticks(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    // The board as recording started.
+    pub fn load_board(&self, registry: Arc<ElementRegistry>) -> Result<SandBoard, SaveError> {
+        SandBoard::load_with_registry(self.board.as_slice(), registry)
+    }
+
+    pub fn replay(&self) -> Replay<'_> {
+        Replay { recording: self, tick: 0, next_action: 0 }
+    }
+
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<(), ReplayError> {
+        writer.write_all(&REPLAY_MAGIC)?;
+        writer.write_all(&REPLAY_VERSION.to_le_bytes())?;
+        write_bytes(&mut writer, &self.board)?;
+        writer.write_all(&(self.actions.len() as u32).to_le_bytes())?;
+        for (tick, action) in self.actions.iter() {
+            writer.write_all(&(*tick as u64).to_le_bytes())?;
+            match action {
+                Action::Paint { x, y, size, element, emits } => {
+                    writer.write_all(&[0])?;
+                    for value in [x, y, size] {
+                        writer.write_all(&(*value as u32).to_le_bytes())?;
+                    }
+                    writer.write_all(&[element.id(), emits.id()])?;
+                },
+                Action::FinishEdit => writer.write_all(&[1])?,
+                Action::SetEdges(edges) => {
+                    writer.write_all(&[2])?;
+                    for edge in [edges.top, edges.bottom, edges.left, edges.right] {
+                        let (mode, element_id) = edge.id();
+                        writer.write_all(&[mode, element_id])?;
+                    }
+                },
+                Action::Reset => writer.write_all(&[3])?,
+                Action::Undo => writer.write_all(&[4])?,
+                Action::Redo => writer.write_all(&[5])?,
+                Action::Load(bytes) => {
+                    writer.write_all(&[6])?;
+                    write_bytes(&mut writer, bytes)?;
+                },
+            }
+        }
+        writer.write_all(&(self.fingerprints.len() as u64).to_le_bytes())?;
+        for fingerprint in self.fingerprints.iter() {
+            writer.write_all(&fingerprint.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<R: Read>(mut reader: R) -> Result<Recording, ReplayError> {
+        if read_array::<_, 8>(&mut reader)? != REPLAY_MAGIC {
+            return Err(ReplayError::BadMagic)
+        }
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+        if version == 0 || version > REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version))
+        }
+        let board = read_bytes(&mut reader)?;
+
+        let action_count = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+        let mut actions = Vec::new();
+        for index in 0..action_count {
+            let tick = u64::from_le_bytes(read_array(&mut reader)?) as usize;
+            let [kind] = read_array(&mut reader)?;
+            let action = match kind {
+                0 => {
+                    let x = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+                    let y = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+                    let size = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+                    let [element_id, emits_id] = read_array(&mut reader)?;
+                    let (Some(element), Some(emits)) = (ElementType::from_id(element_id), ElementType::from_id(emits_id)) else {
+                        return Err(ReplayError::BadAction { index })
+                    };
+                    Action::Paint { x, y, size, element, emits }
+                },
+                1 => Action::FinishEdit,
+                2 => {
+                    let mut edges = [EdgeMode::Solid; 4];
+                    for edge in edges.iter_mut() {
+                        let [mode, element_id] = read_array(&mut reader)?;
+                        *edge = EdgeMode::from_id(mode, element_id).ok_or(ReplayError::BadAction { index })?;
+                    }
+                    let [top, bottom, left, right] = edges;
+                    Action::SetEdges(Edges { top, bottom, left, right })
+                },
+                3 => Action::Reset,
+                4 => Action::Undo,
+                5 => Action::Redo,
+                6 => Action::Load(read_bytes(&mut reader)?),
+                _ => return Err(ReplayError::BadAction { index }),
+            };
+            actions.push((tick, action));
+        }
+
+        let tick_count = u64::from_le_bytes(read_array(&mut reader)?);
+        let mut fingerprints = Vec::new();
+        for _ in 0..tick_count {
+            fingerprints.push(u64::from_le_bytes(read_array(&mut reader)?));
+        }
+        Ok(Recording { board, actions, fingerprints })
+    }
+}
+
+
+// Plays a recording back onto a board loaded with Recording::load_board.
+pub struct Replay<'a> {
+    recording: &'a Recording,
+    tick: usize,
+    next_action: usize,
+}
+
+impl Replay<'_> {
+    pub fn is_finished(&self) -> bool {
+        self.tick == self.recording.ticks()
+    }
+
+    // Does the actions from before the next tick, then ticks. Past the end of
+    // the recording it just ticks.
+    pub fn step(&mut self, sand_board: &mut SandBoard) -> Result<(), ReplayError> {
+        self.catch_up(sand_board)?;
+        sand_board.tick();
+        if let Some(fingerprint) = self.recording.fingerprints.get(self.tick) {
+            self.tick += 1;
+            if sand_board.fingerprint() != *fingerprint {
+                return Err(ReplayError::Diverged { tick: self.tick })
+            }
+        }
+        Ok(())
+    }
+
+    // Does the actions recorded after the last tick, if any.
+    pub fn finish(&mut self, sand_board: &mut SandBoard) -> Result<(), ReplayError> {
+        self.catch_up(sand_board)
+    }
+
+    fn catch_up(&mut self, sand_board: &mut SandBoard) -> Result<(), ReplayError> {
+        while let Some((tick, action)) = self.recording.actions.get(self.next_action) {
+            if *tick > self.tick {
+                break
+            }
+            action.apply(sand_board)?;
+            self.next_action += 1;
+        }
+        Ok(())
+    }
+}
+
+
+impl SandBoard {
+    // A hash of everything on the board (FNV-1a, so it's the same on every
+    // build and platform).
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut add = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        add(&[self.update_cycle as u8]);
+        for granule in self.granules.iter() {
+            add(&[
+                granule.element_type.id(),
+                granule.state.id(),
+                granule.moisture,
+                granule.hunger,
+                granule.growth,
+                granule.direction as u8 | (granule.update_toggle as u8) << 1,
+                granule.velocity_x as u8,
+                granule.velocity_y as u8,
+            ]);
+            add(&granule.temperature.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), ReplayError> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, ReplayError> {
+    let length = u32::from_le_bytes(read_array(reader)?) as u64;
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(ReplayError::Save(SaveError::Truncated))
+    }
+    Ok(bytes)
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], ReplayError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+
+#[cfg(test)]
+mod replay_test {
+    use super::*;
+
+    // A short session: some painting with a stroke over several ticks, the
+    // floor opened up, an undo and a reset that's taken back, and undoing
+    // further than the recording goes.
+    fn record_session(threads: usize) -> (Recording, SandBoard) {
+        let mut sand_board = SandBoard::with_seed(64, 48, 21);
+        sand_board.set_threads(threads);
+        sand_board.randomize_ocean();
+        for _ in 0..10 {
+            sand_board.tick();
+        }
+        sand_board.add_granules(60, 20, 3, ElementType::Wall);
+        let mut recording = Recording::start(&mut sand_board).unwrap();
+        let paint = |x, element| Action::Paint { x, y: 8, size: 3, element, emits: ElementType::Sand };
+        for tick in 0..60 {
+            // the game finishes the edit on every frame the mouse isn't down
+            if tick < 20 {
+                recording.act(&mut sand_board, paint(10 + tick, ElementType::Water)).unwrap();
+            }
+            else {
+                recording.act(&mut sand_board, Action::FinishEdit).unwrap();
+            }
+            match tick {
+                25 => recording.act(&mut sand_board, paint(40, ElementType::Emitter)).unwrap(),
+                30 => recording.act(&mut sand_board, Action::SetEdges(Edges { bottom: EdgeMode::Void, ..Edges::default() })).unwrap(),
+                40 => recording.act(&mut sand_board, Action::Undo).unwrap(),
+                50 => {
+                    recording.act(&mut sand_board, Action::Reset).unwrap();
+                    recording.act(&mut sand_board, Action::Undo).unwrap();
+                },
+                // the water stroke, and then nothing: the wall was painted
+                // before the recording started
+                55 => {
+                    recording.act(&mut sand_board, Action::Undo).unwrap();
+                    recording.act(&mut sand_board, Action::Undo).unwrap();
+                },
+                _ => {},
+            }
+            recording.tick(&mut sand_board);
+        }
+        recording.act(&mut sand_board, paint(30, ElementType::Wall)).unwrap();
+        (recording, sand_board)
+    }
+
+    #[test]
+    fn replays_match_what_was_recorded() {
+        let (recording, recorded) = record_session(1);
+        // the stroke only finishes once, after its last paint
+        let finishes = recording.actions.iter().filter(|(_, action)| *action == Action::FinishEdit).count();
+        assert_eq!(finishes, 2);
+
+        let mut saved = Vec::new();
+        recording.save(&mut saved).unwrap();
+        let loaded = Recording::load(saved.as_slice()).unwrap();
+        assert_eq!(loaded.actions, recording.actions);
+        assert_eq!(loaded.ticks(), 60);
+
+        let mut sand_board = loaded.load_board(ElementRegistry::shared_default()).unwrap();
+        sand_board.set_threads(4);
+        let mut replay = loaded.replay();
+        while !replay.is_finished() {
+            replay.step(&mut sand_board).unwrap();
+        }
+        replay.finish(&mut sand_board).unwrap();
+        assert!(sand_board.granules == recorded.granules);
+        assert_eq!(sand_board.edges, recorded.edges);
+
+        // the board recorded from kept its history
+        let mut recorded = recorded;
+        recording.stop(&mut recorded);
+        let wall = |sand_board: &SandBoard| sand_board.get_surrounding(60, 20)[1][1].element_type == ElementType::Wall;
+        assert!(wall(&recorded));
+        // the last stroke, then the wall from before recording
+        assert!(recorded.undo());
+        assert!(recorded.undo());
+        assert!(!wall(&recorded));
+    }
+
+    #[test]
+    fn replays_that_drift_are_caught() {
+        let (recording, _) = record_session(1);
+        let mut sand_board = recording.load_board(ElementRegistry::shared_default()).unwrap();
+        let mut replay = recording.replay();
+        for _ in 0..5 {
+            replay.step(&mut sand_board).unwrap();
+        }
+        // something the recording doesn't know about
+        sand_board.add_granules(50, 5, 2, ElementType::Sand);
+        assert!(matches!(replay.step(&mut sand_board), Err(ReplayError::Diverged { tick: 6 })));
+
+        let mut saved = Vec::new();
+        recording.save(&mut saved).unwrap();
+        saved.truncate(saved.len() - 4);
+        assert!(matches!(Recording::load(saved.as_slice()), Err(ReplayError::Save(SaveError::Truncated))));
+    }
+}
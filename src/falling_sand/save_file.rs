@@ -0,0 +1,404 @@
+// Saving and loading boards.
+//
+// File layout (all integers little endian):
+//
+//   magic         8 bytes   "SANDBRD\0"
+//   version       u16       SAVE_VERSION
+//   width         u32
+//   height        u32
+//   edges         4 * 2 u8  top, bottom, left and right, each its mode (0
+//                           solid, 1 void, 2 wrap, 3 source) and the
+//                           element id a source spawns, 0 for the others
+//                           (version 4 and up)
+//   update_cycle  u8        0 or 1
+//   seed          u64       the seed the board was created with
+//   granules      width * height records, row by row from the top left:
+//       element_type  u8    ElementType::id(), custom elements (128 and up)
+//                           must be defined in the loading board's registry
+//       state         u8    State::id()
+//       moisture      u8
+//       hunger        u8
+//       growth        u8
+//       flags         u8    bit 0: direction, bit 1: update_toggle
+//       temperature   i16   tenths of a degree celsius (version 2 and up)
+//       velocity_x    i8    sixteenths of a cell per tick (version 3 and up)
+//       velocity_y    i8
+//
+// Version 1 files have no temperature, their granules load at the
+// temperature their definition starts them at. Granules from files before
+// version 3 load standing still. Files before version 4 have a single
+// is_floor byte (0 or 1) where the edges are, and load with solid edges and
+// a void bottom if it was 0.
+//
+// The seed is stored so a loaded board keeps producing the same kind of run,
+// but the rng restarts from it, so a loaded board won't continue the exact
+// random sequence of the board that was saved.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use super::SandBoard;
+use super::chunks::ChunkMap;
+use super::pressure::LevelSearch;
+use super::edges::{EdgeMode, Edges, UnpairedWrap};
+use super::elements::{Element, ElementType, State, AMBIENT_TEMPERATURE};
+use super::element_registry::ElementRegistry;
+
+pub const SAVE_MAGIC: [u8; 8] = *b"SANDBRD\0";
+pub const SAVE_VERSION: u16 = 4;
+
+// boards bigger than this are almost certainly a corrupt header, and
+// allocating for them would take the game down before we could complain
+const MAX_SAVE_CELLS: u64 = 1 << 28;
+
+const FLAG_DIRECTION: u8 = 1;
+const FLAG_UPDATE_TOGGLE: u8 = 1 << 1;
+
+const RECORD_LENGTH: usize = 10;
+
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidDimensions { width: u32, height: u32 },
+    DimensionMismatch { expected: (usize, usize), found: (usize, usize) },
+    UnknownElement { id: u8, index: usize },
+    UnknownState { id: u8, index: usize },
+    InvalidEdge { mode: u8, element_id: u8 },
+    UnpairedWrap(UnpairedWrap),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "i/o error: {}", error),
+            SaveError::BadMagic => write!(f, "not a sand board file"),
+            SaveError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save version {} (newest supported is {})", version, SAVE_VERSION)
+            },
+            SaveError::Truncated => write!(f, "file ended early"),
+            SaveError::InvalidDimensions { width, height } => {
+                write!(f, "invalid board size {}x{}", width, height)
+            },
+            SaveError::DimensionMismatch { expected, found } => {
+                write!(f, "board is {}x{} but the file is {}x{}", expected.0, expected.1, found.0, found.1)
+            },
+            SaveError::UnknownElement { id, index } => {
+                write!(f, "unknown element id {} at cell {}", id, index)
+            },
+            SaveError::UnknownState { id, index } => {
+                write!(f, "unknown state id {} at cell {}", id, index)
+            },
+            SaveError::InvalidEdge { mode, element_id } => {
+                write!(f, "invalid edge mode {} (element id {})", mode, element_id)
+            },
+            SaveError::UnpairedWrap(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Io(error) => Some(error),
+            SaveError::UnpairedWrap(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            return SaveError::Truncated
+        }
+        SaveError::Io(error)
+    }
+}
+
+impl From<UnpairedWrap> for SaveError {
+    fn from(error: UnpairedWrap) -> Self {
+        SaveError::UnpairedWrap(error)
+    }
+}
+
+
+impl SandBoard {
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<(), SaveError> {
+        writer.write_all(&SAVE_MAGIC)?;
+        writer.write_all(&SAVE_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.width as u32).to_le_bytes())?;
+        writer.write_all(&(self.height as u32).to_le_bytes())?;
+        for edge in [self.edges.top, self.edges.bottom, self.edges.left, self.edges.right] {
+            let (mode, element_id) = edge.id();
+            writer.write_all(&[mode, element_id])?;
+        }
+        writer.write_all(&[self.update_cycle as u8])?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+
+        let mut record = Vec::with_capacity(self.granules.len() * RECORD_LENGTH);
+        for granule in self.granules.iter() {
+            let mut flags = 0;
+            if granule.direction {
+                flags |= FLAG_DIRECTION;
+            }
+            if granule.update_toggle {
+                flags |= FLAG_UPDATE_TOGGLE;
+            }
+            record.extend_from_slice(&[
+                granule.element_type.id(),
+                granule.state.id(),
+                granule.moisture,
+                granule.hunger,
+                granule.growth,
+                flags,
+            ]);
+            record.extend_from_slice(&granule.temperature.to_le_bytes());
+            record.extend_from_slice(&[granule.velocity_x as u8, granule.velocity_y as u8]);
+        }
+        writer.write_all(&record)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<R: Read>(reader: R) -> Result<SandBoard, SaveError> {
+        SandBoard::load_with_registry(reader, ElementRegistry::shared_default())
+    }
+
+    // Loads a board that may contain custom elements from a definitions file.
+    pub fn load_with_registry<R: Read>(mut reader: R, registry: Arc<ElementRegistry>) -> Result<SandBoard, SaveError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != SAVE_MAGIC {
+            return Err(SaveError::BadMagic)
+        }
+
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+        if version == 0 || version > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version))
+        }
+
+        let width = u32::from_le_bytes(read_array(&mut reader)?);
+        let height = u32::from_le_bytes(read_array(&mut reader)?);
+        if width == 0 || height == 0 || width as u64 * height as u64 > MAX_SAVE_CELLS {
+            return Err(SaveError::InvalidDimensions { width, height })
+        }
+        let mut edges = [EdgeMode::Solid; 4];
+        if version >= 4 {
+            for edge in edges.iter_mut() {
+                let [mode, element_id] = read_array(&mut reader)?;
+                *edge = EdgeMode::from_id(mode, element_id)
+                    .filter(|edge| match edge {
+                        EdgeMode::Source(element_type) => registry.get(*element_type).is_some(),
+                        _ => true,
+                    })
+                    .ok_or(SaveError::InvalidEdge { mode, element_id })?;
+            }
+        }
+        else {
+            let [is_floor] = read_array(&mut reader)?;
+            if is_floor == 0 {
+                edges[1] = EdgeMode::Void;
+            }
+        }
+        let [update_cycle] = read_array(&mut reader)?;
+        let seed = u64::from_le_bytes(read_array(&mut reader)?);
+
+        let mut sand_board = SandBoard::with_seed(width as usize, height as usize, seed);
+        sand_board.set_registry(registry);
+        let [top, bottom, left, right] = edges;
+        sand_board.set_edges(Edges { top, bottom, left, right })?;
+        sand_board.update_cycle = update_cycle != 0;
+
+        for index in 0..sand_board.granules.len() {
+            let [type_id, state_id, moisture, hunger, growth, flags] = read_array(&mut reader)?;
+            let element_type = ElementType::from_id(type_id)
+                .filter(|element_type| sand_board.registry.get(*element_type).is_some())
+                .ok_or(SaveError::UnknownElement { id: type_id, index })?;
+            let state = State::from_id(state_id)
+                .ok_or(SaveError::UnknownState { id: state_id, index })?;
+            let temperature = if version >= 2 {
+                i16::from_le_bytes(read_array(&mut reader)?)
+            }
+            else {
+                sand_board.registry.get(element_type).map_or(AMBIENT_TEMPERATURE, |definition| definition.temperature)
+            };
+            let [velocity_x, velocity_y] = if version >= 3 {
+                read_array(&mut reader)?
+            }
+            else {
+                [0, 0]
+            };
+            sand_board.granules[index] = Element {
+                element_type,
+                state,
+                update_toggle: flags & FLAG_UPDATE_TOGGLE != 0,
+                moisture,
+                hunger,
+                growth,
+                direction: flags & FLAG_DIRECTION != 0,
+                temperature,
+                velocity_x: velocity_x as i8,
+                velocity_y: velocity_y as i8,
+            };
+        }
+        Ok(sand_board)
+    }
+
+    // Loads a save over this board in place. The file has to match the
+    // board's size, so the window and the pen mapping stay valid.
+    pub fn load_into<R: Read>(&mut self, reader: R) -> Result<(), SaveError> {
+        let mut loaded = SandBoard::load_with_registry(reader, self.registry.clone())?;
+        if loaded.width != self.width || loaded.height != self.height {
+            return Err(SaveError::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (loaded.width, loaded.height),
+            })
+        }
+        loaded.threads = self.threads;
+        *self = loaded;
+        Ok(())
+    }
+
+    // Leaves the board ticking just as it would after loading its own save,
+    // without replacing it: the rng starts again from the seed and every
+    // chunk wakes, but the history is kept.
+    pub(super) fn restart(&mut self) {
+        self.reseed(self.seed);
+        self.chunks = ChunkMap::new(self.width, self.height, Edges::default());
+        self.level_search = LevelSearch::default();
+        self.right_sand = 0;
+        self.left_sand = 0;
+        self.unchanged = 0;
+        self.unaccounted = 0;
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], SaveError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+
+#[cfg(test)]
+mod save_file_test {
+    use super::*;
+    use super::super::elements::degrees;
+
+    // magic, version, width and height come first
+    const EDGES_START: usize = 18;
+
+    fn saved_bytes(sand_board: &SandBoard) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        sand_board.save(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut sand_board = SandBoard::with_seed(30, 20, 99);
+        sand_board.randomize_ocean();
+        sand_board.add_granules(10, 5, 3, ElementType::Egg);
+        sand_board.add_emitters(25, 3, 1, ElementType::Sand);
+        sand_board.edges.bottom = EdgeMode::Void;
+        sand_board.edges.left = EdgeMode::Source(ElementType::Water);
+        for _ in 0..50 {
+            sand_board.tick();
+        }
+
+        let loaded = SandBoard::load(saved_bytes(&sand_board).as_slice()).unwrap();
+        assert_eq!(loaded.width, 30);
+        assert_eq!(loaded.height, 20);
+        assert_eq!(loaded.edges, sand_board.edges);
+        assert_eq!(loaded.update_cycle, sand_board.update_cycle);
+        assert_eq!(loaded.seed(), 99);
+        assert!(loaded.granules == sand_board.granules);
+    }
+
+    #[test]
+    fn version_1_files_load_at_starting_temperature() {
+        let mut sand_board = SandBoard::with_seed(10, 10, 1);
+        sand_board.add_granules(5, 5, 2, ElementType::Sand);
+        sand_board.set_temperature_region(0, 0, 10, 10, degrees(300));
+        let bytes = saved_bytes(&sand_board);
+
+        // strip the temperatures back out to get the old layout, and swap
+        // the edges for an is_floor of 0
+        let header_length = bytes.len() - 10 * 10 * RECORD_LENGTH;
+        let mut old_bytes = bytes[..EDGES_START].to_vec();
+        old_bytes[8..10].copy_from_slice(&1u16.to_le_bytes());
+        old_bytes.push(0);
+        old_bytes.extend_from_slice(&bytes[EDGES_START + 8..header_length]);
+        for record in bytes[header_length..].chunks(RECORD_LENGTH) {
+            old_bytes.extend_from_slice(&record[..6]);
+        }
+
+        let loaded = SandBoard::load(old_bytes.as_slice()).unwrap();
+        assert_eq!(loaded.edges.bottom, EdgeMode::Void);
+        assert_eq!(loaded.edges.top, EdgeMode::Solid);
+        assert_eq!(loaded.get_temperature(5, 5), Some(AMBIENT_TEMPERATURE));
+        assert_eq!(loaded.granules[55].element_type, sand_board.granules[55].element_type);
+    }
+
+    #[test]
+    fn truncated_file() {
+        let sand_board = SandBoard::with_seed(10, 10, 1);
+        let bytes = saved_bytes(&sand_board);
+        for length in [0, 4, 12, bytes.len() - 1] {
+            let result = SandBoard::load(&bytes[..length]);
+            assert!(matches!(result, Err(SaveError::Truncated)), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn unknown_element() {
+        let sand_board = SandBoard::with_seed(10, 10, 1);
+        let mut bytes = saved_bytes(&sand_board);
+        let header_length = bytes.len() - 10 * 10 * RECORD_LENGTH;
+        bytes[header_length + RECORD_LENGTH * 3] = 200;
+        let result = SandBoard::load(bytes.as_slice());
+        assert!(matches!(result, Err(SaveError::UnknownElement { id: 200, index: 3 })));
+    }
+
+    #[test]
+    fn custom_elements_need_their_definitions() {
+        let registry = Arc::new(ElementRegistry::parse("[oil]\ncolor = #3A2A10\nbehaviour = liquid").unwrap());
+        let mut sand_board = SandBoard::with_seed(10, 10, 1);
+        sand_board.set_registry(registry.clone());
+        sand_board.add_granules(5, 5, 2, ElementType::Custom(0));
+        let bytes = saved_bytes(&sand_board);
+
+        let loaded = SandBoard::load_with_registry(bytes.as_slice(), registry).unwrap();
+        assert!(loaded.granules == sand_board.granules);
+        assert!(matches!(SandBoard::load(bytes.as_slice()), Err(SaveError::UnknownElement { id: 128, .. })));
+    }
+
+    #[test]
+    fn bad_header() {
+        let sand_board = SandBoard::with_seed(10, 10, 1);
+        let mut bytes = saved_bytes(&sand_board);
+        bytes[0] = b'X';
+        assert!(matches!(SandBoard::load(bytes.as_slice()), Err(SaveError::BadMagic)));
+
+        let mut bytes = saved_bytes(&sand_board);
+        bytes[8] = 0xFF;
+        assert!(matches!(SandBoard::load(bytes.as_slice()), Err(SaveError::UnsupportedVersion(_))));
+
+        let mut bytes = saved_bytes(&sand_board);
+        bytes[EDGES_START + 2..EDGES_START + 4].copy_from_slice(&[3, 200]);
+        assert!(matches!(SandBoard::load(bytes.as_slice()), Err(SaveError::InvalidEdge { mode: 3, element_id: 200 })));
+    }
+
+    #[test]
+    fn dimension_mismatch() {
+        let sand_board = SandBoard::with_seed(10, 10, 1);
+        let mut other = SandBoard::with_seed(12, 10, 1);
+        let result = other.load_into(saved_bytes(&sand_board).as_slice());
+        assert!(matches!(result, Err(SaveError::DimensionMismatch { expected: (12, 10), found: (10, 10) })));
+    }
+}
@@ -1,4 +1,9 @@
 
+use std::path::Path;
+
+use image::error::{LimitError, LimitErrorKind};
+use image::{ImageError, ImageResult, RgbaImage};
+
 use crate::falling_sand::elements::*;
 pub struct ScreenTexture {
     pub pixel_data: Vec<u8>,
@@ -38,11 +43,36 @@ impl ScreenTexture {
         
     }
 
+    // Copies the texture into an image, blowing each pixel up into a
+    // scale x scale block (nearest neighbour). A scale of 0 is treated as 1.
+    // Fails if the scaled up image would be too big to address.
+    pub fn to_image(&self, scale: u32) -> ImageResult<RgbaImage> {
+        let scale = scale.max(1);
+        let scaled = |length: usize| u32::try_from(length).ok().and_then(|length| length.checked_mul(scale));
+        let (Some(width), Some(height)) = (scaled(self.dimensions.0), scaled(self.dimensions.1)) else {
+            return Err(ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError)));
+        };
+        Ok(RgbaImage::from_fn(width, height, |x, y| {
+            let pixel_index = self.get_pixel_index((x / scale) as usize, (y / scale) as usize);
+            let mut color = [0; 4];
+            color.copy_from_slice(&self.pixel_data[pixel_index..pixel_index + 4]);
+            image::Rgba(color)
+        }))
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, scale: u32) -> ImageResult<()> {
+        self.to_image(scale)?.save_with_format(path, image::ImageFormat::Png)
+    }
+
     pub fn get_pixel_index(&self, x: usize, y: usize) -> usize {
         (y * self.dimensions.0 + x) * 4
     }
 
+    // Pixels off the texture are skipped, so shapes are clipped at its edges.
     pub fn draw_pixel_at_coordinate(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        if x >= self.dimensions.0 || y >= self.dimensions.1 {
+            return
+        }
         let pixel_index = self.get_pixel_index(x, y);
         self.pixel_data[pixel_index] = color[0];
         self.pixel_data[pixel_index + 1] = color[1];
@@ -143,6 +173,53 @@ impl ScreenTexture {
 
 
 
+#[cfg(test)]
+mod screen_texture_test {
+    use super::*;
+
+    #[test]
+    fn upscaled_image() {
+        let texture = ScreenTexture {
+            pixel_data: vec![
+                1, 2, 3, 255,   4, 5, 6, 255,
+                7, 8, 9, 255,   10, 11, 12, 255,
+            ],
+            dimensions: (2, 2),
+        };
+        let image = texture.to_image(3).unwrap();
+        assert_eq!(image.dimensions(), (6, 6));
+        assert_eq!(image.get_pixel(0, 0).0, [1, 2, 3, 255]);
+        assert_eq!(image.get_pixel(2, 2).0, [1, 2, 3, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [4, 5, 6, 255]);
+        assert_eq!(image.get_pixel(5, 5).0, [10, 11, 12, 255]);
+        assert_eq!(texture.to_image(0).unwrap().dimensions(), (2, 2));
+    }
+
+    #[test]
+    fn oversized_scale_is_an_error() {
+        let texture = ScreenTexture {
+            pixel_data: vec![0; 3 * 2 * 4],
+            dimensions: (3, 2),
+        };
+        assert!(matches!(texture.to_image(u32::MAX / 2), Err(ImageError::Limits(_))));
+        let path = std::env::temp_dir().join("sand_oversized_scale.png");
+        assert!(matches!(texture.save_png(&path, u32::MAX / 2), Err(ImageError::Limits(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rects_are_clipped_to_the_texture() {
+        let mut texture = ScreenTexture {
+            pixel_data: vec![0; 4 * 4 * 4],
+            dimensions: (4, 4),
+        };
+        texture.print_filled_rect((2, 3), (5, 5), COLOR_FONT);
+        texture.print_empty_rect((10, 10), (2, 2), COLOR_FONT);
+        let lit: Vec<usize> = (0..16).filter(|pixel| texture.pixel_data[pixel * 4] != 0).collect();
+        assert_eq!(lit, vec![14, 15]);
+    }
+}
+
 
 const COLOR_FONT: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
 
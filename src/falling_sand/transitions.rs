@@ -0,0 +1,108 @@
+// Changes of state. Each element's definition lists the elements it turns
+// into and when: water freezes into ice below zero and boils into steam above
+// a hundred, ice and snow melt back into water, steam condenses once it has
+// cooled down (see builtin_definitions). Definitions files can add their own
+// (see element_registry).
+//
+// A transition happens as soon as its conditions hold, so a freezing pond
+// freezes as fast as the cold gets into it rather than whenever a roll
+// comes up. That also means nothing is left waiting to change in a chunk
+// that has gone to sleep. The new element keeps the old one's temperature.
+
+use super::SandBoard;
+use super::element_registry::Transition;
+
+
+impl SandBoard {
+    // Turns the granule at `index` into whatever its first transition that
+    // applies says. Returns whether it changed.
+    pub(super) fn transition(&mut self, index: usize) -> bool {
+        let transitions = self.registry.transitions(self.granules[index].element_type);
+        let Some(transition) = transitions.iter().find(|transition| self.applies(index, transition)).copied() else {
+            return false
+        };
+        let temperature = self.granules[index].temperature;
+        self.granules[index] = self.new_element(transition.into);
+        self.granules[index].temperature = temperature;
+        self.granules[index].update_toggle = self.update_cycle;
+        true
+    }
+
+    fn applies(&self, index: usize, transition: &Transition) -> bool {
+        let temperature = self.granules[index].temperature;
+        if transition.above.is_some_and(|above| temperature <= above) || transition.below.is_some_and(|below| temperature >= below) {
+            return false
+        }
+        let Some(touching) = transition.touching else {
+            return true
+        };
+        let (x, y) = self.get_coordinates_from_index(index);
+        [(0, -1), (-1, 0), (1, 0), (0, 1)].into_iter().any(|(x_relative, y_relative)| {
+            let (new_x, new_y) = (x as i64 + x_relative, y as i64 + y_relative);
+            self.is_in_coordinate_bounds_signed(new_x, new_y)
+                && self.granules[self.get_index_from_coordinates(new_x as usize, new_y as usize)].element_type == touching
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod transitions_test {
+    use super::*;
+    use super::super::elements::{degrees, ElementType};
+
+    fn count(sand_board: &SandBoard, element_type: ElementType) -> usize {
+        sand_board.granules.iter().filter(|granule| granule.element_type == element_type).count()
+    }
+
+    #[test]
+    fn cold_walls_freeze_water_and_ice_melts_again() {
+        let mut sand_board = SandBoard::with_seed(32, 32, 5);
+        for x in 0..32 {
+            sand_board.add_granule(x, 31, ElementType::Wall);
+            for y in 26..31 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        sand_board.set_temperature_region(0, 31, 32, 1, degrees(-60));
+        for _ in 0..300 {
+            sand_board.tick();
+            // the wall stays cold, as if something were cooling it
+            sand_board.set_temperature_region(0, 31, 32, 1, degrees(-60));
+        }
+        let ice = count(&sand_board, ElementType::Ice);
+        assert!(ice >= 32, "only {} ice", ice);
+
+        // left alone, the wall and the ice warm up to the air around them
+        sand_board.set_temperature_region(0, 0, 32, 32, degrees(20));
+        sand_board.tick();
+        assert_eq!(count(&sand_board, ElementType::Ice), 0);
+        assert_eq!(count(&sand_board, ElementType::Water), 5 * 32);
+    }
+
+    #[test]
+    fn hot_walls_boil_water_into_steam_that_condenses() {
+        let mut sand_board = SandBoard::with_seed(32, 64, 6);
+        for x in 0..32 {
+            sand_board.add_granule(x, 63, ElementType::Wall);
+            for y in 60..63 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        let mut steam_seen = 0;
+        let mut highest_water = 63;
+        for _ in 0..400 {
+            sand_board.set_temperature_region(0, 63, 32, 1, degrees(400));
+            sand_board.tick();
+            steam_seen = steam_seen.max(count(&sand_board, ElementType::Steam));
+            for (index, granule) in sand_board.granules.iter().enumerate() {
+                if granule.element_type == ElementType::Water {
+                    highest_water = highest_water.min(index / 32);
+                }
+            }
+        }
+        assert!(steam_seen > 0);
+        // steam that rose off the pool came back down as water
+        assert!(highest_water < 55, "water never got above row {}", highest_water);
+    }
+}
@@ -1,34 +1,166 @@
 use rand::prelude::*;
 use winit::platform::modifier_supplement::KeyEventExtModifierSupplement;
-use crate::SandBoard;
-
-use super::{Element, ElementType};
-
+use super::SandBoard;
+
+use super::ElementType;
+use super::elements::{State, MAX_SPEED, VELOCITY_SCALE};
+use super::element_registry::Behaviour;
+use super::chunks::MAX_REACH;
+use super::edges::EdgeMode;
+use super::weather::{CLOUD_SATURATION, RAINDROP_MOISTURE};
+
+
+
+// the eight cells around a cell
+pub(super) const NEIGHBOURS: [(i64, i64); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+// how much faster a falling granule gets each tick, a quarter of a cell
+const GRAVITY: i8 = VELOCITY_SCALE / 4;
+// a liquid speeds up by this much for each tick it keeps flowing the same way
+const FLOW_ACCELERATION: i8 = 2;
+const MAX_FLOW_SPEED: i8 = 4 * VELOCITY_SCALE;
+
+// fire lit by something burning has this many ticks of fuel
+const BURNING_FUEL: std::ops::Range<u8> = 20..60;
+const SMOKE_CHANCE: f64 = 0.3;
+const ASH_CHANCE: f64 = 0.35;
+// smoke lasts 100 ticks on average
+const SMOKE_DISPERSAL_CHANCE: f64 = 0.01;
+const CLOUD_DRIFT_CHANCE: f64 = 0.2;
+const RAIN_CHANCE: f64 = 0.1;
+
+// how much moisture soil loses for each cell it's passed through, so water
+// soaks further into dirt than into sand
+const DIRT_WICKING_LOSS: u8 = 8;
+const SAND_WICKING_LOSS: u8 = 24;
+// soil open to the air is this much drier than it would be otherwise
+const SOIL_EXPOSED_DRYING: u8 = 32;
+// the least moisture soil needs for a seed on it to sprout, and for grass
+// to keep growing
+const SEED_GERMINATION_MOISTURE: u8 = 64;
+const GRASS_GROWTH_MOISTURE: u8 = 48;
+// soil at least this damp keeps small creatures from drying out
+const DAMP_SOIL_MOISTURE: u8 = 96;
+
+// what moss can grow on
+const MOSS_SURFACES: [ElementType; 3] = [ElementType::Wall, ElementType::Dirt, ElementType::Sand];
+// the chance each tick that soaking wet moss spreads, drier moss spreads
+// less in proportion to its moisture
+const MOSS_GROWTH_CHANCE: f64 = 0.2;
+// moss out of reach of water lasts a few thousand ticks
+const MOSS_DRYING_CHANCE: f64 = 0.05;
+
+// kelp a tadpole has to eat to turn into a frog
+pub(super) const TADPOLE_MEALS: u8 = 8;
+// a tadpole starves after about 2500 ticks without eating
+const TADPOLE_HUNGER_CHANCE: f64 = 0.1;
+// a tadpole out of water dies about this many ticks later
+const TADPOLE_DRYING: u8 = 8;
+const TADPOLE_TURN_CHANCE: f64 = 0.2;
+const FROG_HOP_CHANCE: f64 = 0.1;
+// how far across and up a frog jumps
+const FROG_HOP: (i64, i64) = (3, 2);
+// a frog on land dries out after about 1300 ticks
+const FROG_DRYING_CHANCE: f64 = 0.2;
+// below this much moisture a frog heads back for water
+const FROG_THIRSTY: u8 = 120;
+// a frog in water lays an egg about every 1000 ticks
+const FROG_BREEDING_CHANCE: f64 = 0.25;
+const ISOPOD_CRAWL_CHANCE: f64 = 0.3;
+// the highest step an isopod can climb
+const ISOPOD_STEP: i64 = 2;
+// the chance an isopod blocked by dirt digs through it
+const ISOPOD_BURROW_CHANCE: f64 = 0.05;
+// an isopod starves about 5000 ticks after its last meal
+const ISOPOD_HUNGER_CHANCE: f64 = 0.05;
+// how much hunger eating a granule of detritus takes away
+const ISOPOD_MEAL: u8 = 80;
+// above this much hunger an isopod goes looking for food
+const ISOPOD_HUNGRY: u8 = 100;
+// an isopod that's eaten this many times, and isn't hungry, lays an egg
+const ISOPOD_BROOD_MEALS: u8 = 3;
+// a minnow starves about 5000 ticks after its last meal
+const MINNOW_HUNGER_CHANCE: f64 = 0.05;
+const MINNOW_MEAL: u8 = 80;
+const MINNOW_HUNGRY: u8 = 100;
+pub(super) const MINNOW_BROOD_MEALS: u8 = 4;
+const MINNOW_DIVE_CHANCE: f64 = 0.3;
+// snails only climb one cell at a time, and never dig
+const SNAIL_CRAWL_CHANCE: f64 = 0.05;
+const SNAIL_STEP: i64 = 1;
+// the chance each tick that a snail takes a bite of the moss or kelp it's on
+const SNAIL_GRAZING_CHANCE: f64 = 0.02;
+// a snail starves about 10000 ticks after its last meal
+const SNAIL_HUNGER_CHANCE: f64 = 0.025;
+const SNAIL_MEAL: u8 = 60;
+const SNAIL_HUNGRY: u8 = 100;
+const SNAIL_BROOD_MEALS: u8 = 6;
+// a snail away from anything damp dries out after about 2500 ticks
+const SNAIL_DRYING_CHANCE: f64 = 0.1;
+const SPRINGTAIL_JUMP_CHANCE: f64 = 0.3;
+// the furthest a springtail jumps across and up
+const SPRINGTAIL_JUMP: (i64, i64) = (4, 3);
+// the chance each tick that a springtail wriggles into damp dirt next to it
+const SPRINGTAIL_BURROW_CHANCE: f64 = 0.1;
+// a springtail starves about 1300 ticks after its last meal
+const SPRINGTAIL_HUNGER_CHANCE: f64 = 0.2;
+const SPRINGTAIL_MEAL: u8 = 120;
+const SPRINGTAIL_HUNGRY: u8 = 60;
+const SPRINGTAIL_BROOD_MEALS: u8 = 2;
+// a springtail away from anything damp dries out after about 130 ticks
+const SPRINGTAIL_DRYING: u8 = 2;
 
 
 enum LiquidCollision {
     None,
     Wall,
     OpenSpace(usize),
+    // right up against a void edge
+    OffEdge,
 }
 
 
 impl SandBoard {
 
-    fn get_relative_index(&mut self, index: usize, x_relative: i64, y_relative: i64) -> Option<usize> {
+    // None past the edge of the board, unless the edge wraps
+    pub(super) fn get_relative_index(&mut self, index: usize, x_relative: i64, y_relative: i64) -> Option<usize> {
         let (x, y) = self.get_coordinates_from_index(index);
         let new_x = x as i64 + x_relative;
         let new_y = y as i64 + y_relative;
         if self.is_in_coordinate_bounds_signed(new_x, new_y) {
-            return Some(self.get_index_from_coordinates(new_x as usize, new_y as usize));
-            
+            return Some(self.get_index_from_coordinates(new_x as usize, new_y as usize))
         }
-        else {
-            return None
+        match self.resolve_coordinates(new_x, new_y) {
+            Ok((new_x, new_y)) => Some(self.get_index_from_coordinates(new_x, new_y)),
+            Err(_) => None,
+        }
+    }
+
+    // Follows a straight line from `index` towards the cell at x_relative,
+    // y_relative a cell at a time, and returns the last cell along it that
+    // `can_pass` before the first one that can't, so jumps never go through
+    // anything. None if even the first step is blocked.
+    fn jump_end(&mut self, index: usize, x_relative: i64, y_relative: i64, can_pass: impl Fn(ElementType) -> bool) -> Option<usize> {
+        let steps = x_relative.abs().max(y_relative.abs());
+        let mut end = None;
+        for step in 1..=steps {
+            let Some(next_index) = self.get_relative_index(index, x_relative * step / steps, y_relative * step / steps) else {
+                break
+            };
+            if !can_pass(self.granules[next_index].element_type) {
+                break
+            }
+            end = Some(next_index);
         }
+        end
     }
 
     pub fn granule_tick_new(&mut self, index: usize) {
+        // before the toggle, which static elements never flip, so they
+        // don't skip every other check and sleep through a change
+        if self.transition(index) {
+            return
+        }
         if self.granules[index].update_toggle == self.update_cycle {
             return
         }
@@ -36,18 +168,6 @@ impl SandBoard {
         match element_type {
             ElementType::Empty => {
 
-            },
-            ElementType::Sand => {
-                self.update_sand(index);
-            },
-            ElementType::Water => {
-                self.update_water(index);
-            },
-            ElementType::Wall => {
-
-            },
-            ElementType::Dirt => {
-                self.update_dirt(index);
             },
             ElementType::Seed => {
                 self.update_seed(index);
@@ -64,16 +184,58 @@ impl SandBoard {
             ElementType::Minnow => {
                 self.update_minnow(index);
             },
+            ElementType::Fire => {
+                self.update_fire(index);
+            },
+            ElementType::Smoke => {
+                self.update_smoke(index);
+            },
+            ElementType::Cloud => {
+                self.update_cloud(index);
+            },
+            ElementType::Moss => {
+                self.update_moss(index);
+            },
+            ElementType::Tadpole => {
+                self.update_tadpole(index);
+            },
+            ElementType::Frog => {
+                self.update_frog(index);
+            },
+            ElementType::Isopod => {
+                self.update_isopod(index);
+            },
+            ElementType::Snail => {
+                self.update_snail(index);
+            },
+            ElementType::SpringTail => {
+                self.update_springtail(index);
+            },
+            ElementType::Emitter => {
+                self.update_emitter(index);
+            },
+            ElementType::Drain => {
+                self.update_drain(index);
+            },
             _ => {
-
+                if matches!(element_type, ElementType::Dirt | ElementType::Sand) {
+                    self.update_soil_moisture(index);
+                }
+                // everything without its own update moves the way its
+                // definition says
+                match self.registry.behaviour(element_type) {
+                    Some(Behaviour::Powder) => self.update_powder(index),
+                    Some(Behaviour::Liquid) => self.update_liquid(index),
+                    Some(Behaviour::Gas) => self.update_gas(index),
+                    Some(Behaviour::Static) | None => {},
+                }
             }
 
         }
     }
 
     pub fn is_falling_diagonal(&mut self, index: usize) -> bool {
-        let mut rng = rand::thread_rng();
-        let rng_val: f64 = rng.gen(); 
+        let rng_val: f64 = self.rng.gen(); 
         let mut directions = Vec::new();
         if rng_val > 0.5 {
             directions.push(self.get_relative_index(index, -1, 1));
@@ -86,9 +248,7 @@ impl SandBoard {
 
         for direction in directions {
             if direction.is_some() {
-                if (self.granules[direction.unwrap()].element_type == ElementType::Empty ||
-                    self.granules[direction.unwrap()].element_type == ElementType::Water) &&
-                    self.granules[direction.unwrap()].element_type != self.granules[index].element_type {
+                if self.can_sink_into(index, direction.unwrap()) {
                         let current_granule = self.granules[index];
                         self.granules[index] = self.granules[direction.unwrap()];
                         self.granules[direction.unwrap()] = current_granule;
@@ -101,8 +261,7 @@ impl SandBoard {
     }
 
     pub fn is_falling_diagonal_air(&mut self, index: usize) -> bool {
-        let mut rng = rand::thread_rng();
-        let rng_val: f64 = rng.gen(); 
+        let rng_val: f64 = self.rng.gen(); 
         let mut directions = Vec::new();
         if rng_val > 0.5 {
             directions.push(self.get_relative_index(index, -1, 1));
@@ -129,98 +288,366 @@ impl SandBoard {
     }
     
 
-    fn update_sand(&mut self, index: usize) {
-        let current_granule = self.granules[index];
-        let (x, y) = self.get_coordinates_from_index(index);
-        if let Some(below_index) = self.get_relative_index(index, 0, 1) {
-            let granule_below = self.granules[below_index];
-            if granule_below.element_type == ElementType::Empty || granule_below.element_type == ElementType::Water {
-                self.granules[index] = granule_below;
-                self.granules[below_index] = current_granule;
-                self.granules[below_index].update_toggle = self.update_cycle;
-                return
-            }
+    fn update_powder(&mut self, index: usize) {
+        if self.fall(index) {
+            return
         }
-        else {
-            if !self.is_floor {
-                self.update_granule(x, y, ElementType::Empty);
-            }
+        if self.is_falling_diagonal(index) {
+            return
+        }
+    }
+
+    fn update_liquid(&mut self, index: usize) {
+        if self.fall(index) {
             return
         }
         if self.is_falling_diagonal(index) {
             return
         }
+        
+        self.move_forward(index);
     }
 
-    fn update_dirt(&mut self, index: usize) {
-        let current_granule = self.granules[index];
-        let (x, y) = self.get_coordinates_from_index(index);
-        if let Some(below_index) = self.get_relative_index(index, 0, 1) {
-            let granule_below = self.granules[below_index];
-            if granule_below.element_type == ElementType::Empty || granule_below.element_type == ElementType::Water {
-                self.granules[index] = granule_below;
-                self.granules[below_index] = current_granule;
-                self.granules[below_index].update_toggle = self.update_cycle;
+    // Drops a granule as far as its speed takes it this tick, speeding it up
+    // as it goes. It stops at the first thing it can't sink through, so fast
+    // granules never skip over anything. A liquid that lands turns some of
+    // its fall into sideways speed and splashes out along the ground.
+    fn fall(&mut self, index: usize) -> bool {
+        let falling_speed = self.granules[index].velocity_y;
+        let speed = falling_speed.saturating_add(GRAVITY).min(MAX_SPEED);
+        let mut current_index = index;
+        let mut has_landed = false;
+        // the speed is only rolled once the granule has moved, which saves a
+        // roll for every granule that's lying still
+        let mut cells = 1;
+        let mut moved = 0;
+        while moved < cells {
+            match self.get_relative_index(current_index, 0, 1) {
+                Some(below_index) if self.can_sink_into(current_index, below_index) => {
+                    self.granules.swap(current_index, below_index);
+                    current_index = below_index;
+                    moved += 1;
+                    if moved == 1 {
+                        cells = self.cells_this_tick(speed).max(1);
+                    }
+                },
+                Some(_) => {
+                    has_landed = true;
+                    break
+                },
+                None => {
+                    if self.is_leaving_board(current_index, 0, 1) {
+                        return true
+                    }
+                    has_landed = true;
+                    break
+                },
+            }
+        }
+
+        if !has_landed {
+            self.granules[current_index].velocity_y = speed;
+        }
+        else if falling_speed != 0 {
+            // only granules that were falling are touched, so resting ones
+            // stay exactly as they are and their chunk can go to sleep
+            let granule = &mut self.granules[current_index];
+            if granule.state == State::Liquid {
+                let splash = (falling_speed / 2).min(MAX_FLOW_SPEED);
+                granule.velocity_x = if granule.direction { splash } else { -splash };
+            }
+            granule.velocity_y = 0;
+        }
+        if current_index == index {
+            return false
+        }
+        self.granules[current_index].update_toggle = self.update_cycle;
+        true
+    }
+
+    // How many whole cells something moving at `speed` covers this tick. The
+    // fraction of a cell left over is a chance of going one cell further.
+    fn cells_this_tick(&mut self, speed: i8) -> i64 {
+        let speed = speed.unsigned_abs() as i64;
+        let scale = VELOCITY_SCALE as i64;
+        let mut cells = speed / scale;
+        if self.rng.gen_range(0..scale) < speed % scale {
+            cells += 1;
+        }
+        cells
+    }
+
+
+
+    // Gases rise through empty space, and anything heavier that can be pushed
+    // aside, and spread sideways once they hit a ceiling, the same way liquids
+    // spread along a floor.
+    fn update_gas(&mut self, index: usize) {
+        if self.is_leaving_board(index, 0, -1) {
+            return
+        }
+        if let Some(above_index) = self.get_relative_index(index, 0, -1) {
+            if self.can_rise_into(index, above_index) {
+                let current_granule = self.granules[index];
+                self.granules[index] = self.granules[above_index];
+                self.granules[above_index] = current_granule;
+                self.granules[above_index].update_toggle = self.update_cycle;
                 return
             }
         }
+        if self.is_rising_diagonal(index) {
+            return
+        }
+
+        self.move_forward(index);
+    }
+
+    fn is_rising_diagonal(&mut self, index: usize) -> bool {
+        let rng_val: f64 = self.rng.gen(); 
+        let mut directions = Vec::new();
+        if rng_val > 0.5 {
+            directions.push(self.get_relative_index(index, -1, -1));
+            directions.push(self.get_relative_index(index, 1, -1));
+        } 
         else {
-            if !self.is_floor {
-                self.update_granule(x, y, ElementType::Empty);
+            directions.push(self.get_relative_index(index, 1, -1));
+            directions.push(self.get_relative_index(index, -1, -1));
+        }
+
+        for direction in directions.into_iter().flatten() {
+            if self.can_rise_into(index, direction) {
+                let current_granule = self.granules[index];
+                self.granules[index] = self.granules[direction];
+                self.granules[direction] = current_granule;
+                self.granules[direction].update_toggle = self.update_cycle;
+                return true
+            }
+        }
+        return false
+    }
+
+    // Whether the granule at `index` is heavier than what's at `other_index`
+    // and can sink through it. Only liquids and gases get pushed out of the
+    // way, solids and granules stay put however light they are.
+    pub(super) fn can_sink_into(&self, index: usize, other_index: usize) -> bool {
+        let other = self.granules[other_index];
+        matches!(other.state, State::Liquid | State::Gas) &&
+            self.registry.density(self.granules[index].element_type) > self.registry.density(other.element_type)
+    }
+
+    // Whether the granule at `index` is lighter than what's at `other_index`
+    // and can rise through it. Empty space never holds anything up.
+    fn can_rise_into(&self, index: usize, other_index: usize) -> bool {
+        let other = self.granules[other_index];
+        other.element_type == ElementType::Empty || (matches!(other.state, State::Liquid | State::Gas) &&
+            self.registry.density(self.granules[index].element_type) < self.registry.density(other.element_type))
+    }
+
+    fn is_touching(&self, index: usize, element_type: ElementType) -> bool {
+        self.get_surrounding_from_index(index).iter().flatten().any(|granule| granule.element_type == element_type)
+    }
+
+    // Fire burns in place until its fuel (kept in growth) runs out, lighting
+    // anything flammable around it and giving off smoke. Water puts it out.
+    fn update_fire(&mut self, index: usize) {
+        let (x, y) = self.get_coordinates_from_index(index);
+        if self.is_touching(index, ElementType::Water) {
+            self.update_granule(x, y, ElementType::Smoke);
+            return
+        }
+        // a fire stays as hot as its definition for as long as it burns
+        if let Some(definition) = self.registry.get(ElementType::Fire) {
+            self.granules[index].temperature = definition.temperature;
+        }
+
+        for (x_relative, y_relative) in NEIGHBOURS {
+            let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            let flammability = self.registry.flammability(self.granules[neighbour_index].element_type);
+            // anything wet, like kelp under water, won't catch
+            if flammability == 0 || self.rng.gen::<u8>() >= flammability || self.is_touching(neighbour_index, ElementType::Water) {
+                continue
+            }
+            self.granules[neighbour_index] = self.new_element(ElementType::Fire);
+            self.granules[neighbour_index].growth = self.rng.gen_range(BURNING_FUEL);
+            self.granules[neighbour_index].update_toggle = self.update_cycle;
+        }
+
+        if self.rng.gen::<f64>() < SMOKE_CHANCE {
+            if let Some(above_index) = self.get_relative_index(index, 0, -1) {
+                if self.granules[above_index].element_type == ElementType::Empty {
+                    self.granules[above_index] = self.new_element(ElementType::Smoke);
+                    self.granules[above_index].update_toggle = self.update_cycle;
+                }
             }
+        }
+
+        if self.granules[index].growth > 0 {
+            self.granules[index].growth -= 1;
             return
         }
-        if self.is_falling_diagonal(index) {
+        // burnt out, whatever's left keeps the fire's heat
+        let temperature = self.granules[index].temperature;
+        if self.rng.gen::<f64>() < ASH_CHANCE {
+            self.update_granule(x, y, ElementType::Ash);
+        }
+        else {
+            self.update_granule(x, y, ElementType::Empty);
+        }
+        self.granules[index].temperature = temperature;
+    }
+
+    // Smoke rises like any other gas and thins out as it goes.
+    fn update_smoke(&mut self, index: usize) {
+        if self.rng.gen::<f64>() < SMOKE_DISPERSAL_CHANCE {
+            let (x, y) = self.get_coordinates_from_index(index);
+            self.update_granule(x, y, ElementType::Empty);
             return
         }
+        self.update_gas(index);
     }
 
-    fn update_water(&mut self, index: usize) {
-        let current_granule = self.granules[index];
-        if let Some(below_index) = self.get_relative_index(index, 0, 1) {
-            let granule_below = self.granules[below_index];
-            if granule_below.element_type == ElementType::Empty {
-                self.granules[index] = granule_below;
-                self.granules[below_index] = current_granule;
-                self.granules[below_index].update_toggle = self.update_cycle;
+    // Clouds rise to the top of the open sky and drift along under it. A
+    // cloud soaks up the moisture of any smaller cloud it touches, and rains
+    // once it's saturated, see weather.rs.
+    fn update_cloud(&mut self, index: usize) {
+        if let Some(above_index) = self.get_relative_index(index, 0, -1) {
+            if self.can_rise_into(index, above_index) {
+                self.granules.swap(index, above_index);
+                self.granules[above_index].update_toggle = self.update_cycle;
                 return
             }
         }
-        else {
-            if !self.is_floor {
-                let (x, y) = self.get_coordinates_from_index(index);
-                self.update_granule(x, y, ElementType::Empty);
+
+        let (x_relative, y_relative) = NEIGHBOURS[self.rng.gen_range(0..NEIGHBOURS.len())];
+        if let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) {
+            let neighbour = self.granules[neighbour_index];
+            if neighbour.element_type == ElementType::Cloud && neighbour.moisture <= self.granules[index].moisture {
+                let taken = neighbour.moisture.min(u8::MAX - self.granules[index].moisture);
+                self.granules[index].moisture += taken;
+                self.granules[neighbour_index].moisture -= taken;
+                if self.granules[neighbour_index].moisture == 0 {
+                    self.granules[neighbour_index] = self.new_element(ElementType::Empty);
+                }
             }
-            return
         }
-        if self.is_falling_diagonal(index) {
-            return
+
+        if self.granules[index].moisture >= CLOUD_SATURATION && self.rng.gen_bool(RAIN_CHANCE) {
+            if let Some(below_index) = self.get_relative_index(index, 0, 1) {
+                if self.granules[below_index].element_type == ElementType::Empty {
+                    self.granules[below_index] = self.new_element(ElementType::Water);
+                    self.granules[below_index].update_toggle = self.update_cycle;
+                    self.granules[index].moisture -= RAINDROP_MOISTURE;
+                }
+            }
+        }
+
+        if self.rng.gen_bool(CLOUD_DRIFT_CHANCE) {
+            let x_relative = if self.granules[index].direction { 1 } else { -1 };
+            match self.get_relative_index(index, x_relative, 0) {
+                Some(side_index) if self.granules[side_index].element_type == ElementType::Empty => {
+                    self.granules.swap(index, side_index);
+                    self.granules[side_index].update_toggle = self.update_cycle;
+                },
+                _ => self.granules[index].switch_direction(),
+            }
         }
-        
-        self.move_forward(index);
     }
 
+    // Moss keeps how wet it is in moisture. Touching water soaks it, and it
+    // draws moisture from wetter moss next to it, so a patch stays alive as
+    // far as the water can be wicked along it. It only spreads into cells
+    // that sit against a surface, and dies back to detritus once it dries
+    // out.
+    fn update_moss(&mut self, index: usize) {
+        let (x_relative, y_relative) = NEIGHBOURS[self.rng.gen_range(0..NEIGHBOURS.len())];
+        if let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) {
+            let neighbour = self.granules[neighbour_index];
+            let moisture = self.granules[index].moisture;
+            if neighbour.element_type == ElementType::Water {
+                self.granules[index].moisture = u8::MAX;
+            }
+            else if neighbour.element_type == ElementType::Moss && neighbour.moisture > moisture {
+                self.granules[index].moisture = moisture + (neighbour.moisture - moisture) / 2;
+            }
+            else if neighbour.element_type == ElementType::Empty && self.is_moss_surface(neighbour_index) {
+                let growth_chance = MOSS_GROWTH_CHANCE * moisture as f64 / u8::MAX as f64;
+                if self.rng.gen_bool(growth_chance) {
+                    self.granules[neighbour_index] = self.new_element(ElementType::Moss);
+                    self.granules[neighbour_index].moisture = moisture;
+                    self.granules[neighbour_index].update_toggle = self.update_cycle;
+                }
+            }
+        }
 
+        // moss that's lost its footing dries out every tick
+        if !self.is_moss_surface(index) || self.rng.gen_bool(MOSS_DRYING_CHANCE) {
+            self.granules[index].moisture = self.granules[index].moisture.saturating_sub(1);
+        }
+        if self.granules[index].moisture == 0 {
+            let (x, y) = self.get_coordinates_from_index(index);
+            self.update_granule(x, y, ElementType::Detritus);
+        }
+    }
 
-    fn look_forward(&mut self, index: usize, direction: bool, distance: usize) -> LiquidCollision {
-        let (x, y) = self.get_coordinates_from_index(index);
-        for i in 1..=distance {
-            let mut new_x = x as i64;
-            if !direction {
-                new_x -= i as i64;
+    // Whether a cell sits right up against something moss grows on. Only
+    // the four sides count, so moss creeps around corners instead of
+    // reaching across gaps.
+    fn is_moss_surface(&mut self, index: usize) -> bool {
+        for (x_relative, y_relative) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            if let Some(side_index) = self.get_relative_index(index, x_relative, y_relative) {
+                if MOSS_SURFACES.contains(&self.granules[side_index].element_type) {
+                    return true
+                }
             }
-            else {
-                new_x += i as i64;
+        }
+        false
+    }
+
+    // Soil touching water is soaked, otherwise it's a little drier than the
+    // wettest soil next to it, and drier again where it's open to the air.
+    // Since that only depends on the neighbours, a patch of soil settles
+    // once its water stops moving, and dries out again when the water's gone.
+    fn update_soil_moisture(&mut self, index: usize) {
+        let wicking_loss = if self.granules[index].element_type == ElementType::Sand { SAND_WICKING_LOSS } else { DIRT_WICKING_LOSS };
+        let mut moisture = 0;
+        let mut is_exposed = false;
+        for (x_relative, y_relative) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            let Some(side_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            let side = self.granules[side_index];
+            match side.element_type {
+                ElementType::Water => moisture = u8::MAX,
+                ElementType::Dirt | ElementType::Sand => moisture = moisture.max(side.moisture.saturating_sub(wicking_loss)),
+                ElementType::Empty => is_exposed = true,
+                _ => {},
             }
-            if self.is_in_coordinate_bounds_signed(new_x, x as i64) {
-                let new_index = self.get_index_from_coordinates(new_x as usize, y);
-                if self.granules[new_index].element_type == ElementType::Empty {
+        }
+        if is_exposed {
+            moisture = moisture.saturating_sub(SOIL_EXPOSED_DRYING);
+        }
+        self.granules[index].moisture = moisture;
+    }
+
+    fn look_forward(&mut self, index: usize, direction: bool, distance: usize) -> LiquidCollision {
+        let element_type = self.granules[index].element_type;
+        let x_relative = if direction { 1 } else { -1 };
+        for i in 1..=distance {
+            if let Some(new_index) = self.get_relative_index(index, x_relative * i as i64, 0) {
+                // spreading pushes lighter gases aside, but leaves liquids to
+                // settle into layers by sinking
+                if self.granules[new_index].state == State::Gas && self.can_sink_into(index, new_index) {
                     return LiquidCollision::OpenSpace(new_index)
                 } 
-                else if self.granules[new_index].element_type != ElementType::Water{
+                else if self.granules[new_index].element_type != element_type {
                     return LiquidCollision::Wall
                 }
             }
+            else if i == 1 && self.is_past_void(index, x_relative) {
+                return LiquidCollision::OffEdge
+            }
             else {
                 return LiquidCollision::Wall
             }
@@ -228,6 +655,12 @@ impl SandBoard {
         return LiquidCollision::None
     }
 
+    // whether the cell x_relative along from `index` is off a void edge
+    fn is_past_void(&self, index: usize, x_relative: i64) -> bool {
+        let (x, y) = self.get_coordinates_from_index(index);
+        self.resolve_coordinates(x as i64 + x_relative, y as i64) == Err(EdgeMode::Void)
+    }
+
 
     pub fn move_forward(&mut self, index: usize) -> bool {
         let dispersion = 5;
@@ -235,29 +668,36 @@ impl SandBoard {
         match self.look_forward(index, self.granules[index].direction, dispersion) {
             LiquidCollision::Wall => {
                 self.granules[index].switch_direction();
+                self.granules[index].velocity_x = 0;
                 return false
             },
             LiquidCollision::OpenSpace(new_index) => {
-                let current_granule = self.granules[index];
-                self.granules[index] = self.granules[new_index];
-                self.granules[new_index] = current_granule;
-                self.granules[new_index].update_toggle = self.update_cycle;
+                self.flow(index, new_index, self.granules[index].direction);
+                return true
+            },
+            LiquidCollision::OffEdge => {
+                let (x, y) = self.get_coordinates_from_index(index);
+                self.update_granule(x, y, ElementType::Empty);
                 return true
             },
             LiquidCollision::None => {
                 match self.look_forward(index, !self.granules[index].direction, dispersion) {
                     LiquidCollision::Wall => {
                         self.granules[index].switch_direction();
+                        self.granules[index].velocity_x = 0;
                         return false
                     },
                     LiquidCollision::OpenSpace(new_index) => {
-                        let current_granule = self.granules[index];
-                        self.granules[index] = self.granules[new_index];
-                        self.granules[new_index] = current_granule;
-                        self.granules[new_index].update_toggle = self.update_cycle;
+                        self.flow(index, new_index, !self.granules[index].direction);
+                        return true
+                    },
+                    LiquidCollision::OffEdge => {
+                        let (x, y) = self.get_coordinates_from_index(index);
+                        self.update_granule(x, y, ElementType::Empty);
                         return true
                     },
                     LiquidCollision::None => {
+                        self.granules[index].velocity_x = 0;
                         return false
                     }
                 }
@@ -266,6 +706,39 @@ impl SandBoard {
         
     }
 
+    // Moves a spreading granule into the open space at `new_index`, then on
+    // along the row as far as its sideways speed carries it, stopping at the
+    // first thing in the way. Every tick it keeps going the same way it
+    // speeds up, so water running across a floor picks up pace, and turning
+    // around or running into something loses it all.
+    fn flow(&mut self, index: usize, new_index: usize, moving_right: bool) {
+        self.granules.swap(index, new_index);
+        let x_relative = if moving_right { 1 } else { -1 };
+        let mut speed = self.granules[new_index].velocity_x;
+        if (speed > 0) != moving_right {
+            speed = 0;
+        }
+        speed = speed.unsigned_abs().min(MAX_FLOW_SPEED as u8) as i8;
+        speed = speed.saturating_add(FLOW_ACCELERATION).min(MAX_FLOW_SPEED);
+
+        let mut current_index = new_index;
+        for _ in 0..self.cells_this_tick(speed) {
+            match self.get_relative_index(current_index, x_relative, 0) {
+                Some(next_index) if self.granules[next_index].state == State::Gas && self.can_sink_into(current_index, next_index) => {
+                    self.granules.swap(current_index, next_index);
+                    current_index = next_index;
+                },
+                _ => {
+                    speed = 0;
+                    break
+                },
+            }
+        }
+        self.granules[current_index].velocity_x = speed * x_relative as i8;
+        self.granules[current_index].direction = moving_right;
+        self.granules[current_index].update_toggle = self.update_cycle;
+    }
+
     fn update_seed(&mut self, index: usize) {
         let current_granule = self.granules[index];
         if let Some(below_index) = self.get_relative_index(index, 0, 1) {
@@ -278,34 +751,43 @@ impl SandBoard {
                     return
                 },
                 ElementType::Dirt => {
-                    let mut rng = rand::thread_rng();
                     if let Some(above_index) = self.get_relative_index(index, 0, -1) {
                         if self.granules[above_index].element_type == ElementType::Water {
                             self.granules[index].element_type = ElementType::Kelp;
-                            self.granules[index].growth = rng.gen_range(30..self.height as u8);
-                            self.granules[index].hunger = rng.gen_range(1..20);
+                            self.granules[index].growth = self.rng.gen_range(30..self.height as u8);
+                            self.granules[index].hunger = self.rng.gen_range(1..20);
                             self.granules[below_index].update_toggle = self.update_cycle;
                             return
                         }
                     }
+                    // seeds wait on dry ground until it gets wet
+                    if granule_below.moisture < SEED_GERMINATION_MOISTURE {
+                        self.granules[index].update_toggle = self.update_cycle;
+                        return
+                    }
                     self.granules[index].element_type = ElementType::Grass;
+                    self.granules[index].moisture = granule_below.moisture;
                     self.granules[below_index].update_toggle = self.update_cycle;
                     return
                 },
                 ElementType::Sand => {
-                    let mut rng = rand::thread_rng();
                     if let Some(above_index) = self.get_relative_index(index, 0, -1) {
                         if self.granules[above_index].element_type == ElementType::Water {
                             self.granules[index].element_type = ElementType::Kelp;
-                            self.granules[index].growth = rng.gen_range(30..self.height as u8);
-                            self.granules[index].hunger = rng.gen_range(1..20);
+                            self.granules[index].growth = self.rng.gen_range(30..self.height as u8);
+                            self.granules[index].hunger = self.rng.gen_range(1..20);
                             self.granules[below_index].update_toggle = self.update_cycle;
                             return
                         }
                     }
-                    let val = rng.gen_range(2..20);
+                    if granule_below.moisture < SEED_GERMINATION_MOISTURE {
+                        self.granules[index].update_toggle = self.update_cycle;
+                        return
+                    }
+                    let val = self.rng.gen_range(2..20);
                     self.granules[index].element_type = ElementType::Grass;
                     self.granules[index].growth = val;
+                    self.granules[index].moisture = granule_below.moisture;
                     self.granules[below_index].update_toggle = self.update_cycle;
                     return
                 },
@@ -316,10 +798,7 @@ impl SandBoard {
           
         }
         else {
-            if !self.is_floor {
-                let (x, y) = self.get_coordinates_from_index(index);
-                self.update_granule(x, y, ElementType::Empty);
-            }
+            self.is_leaving_board(index, 0, 1);
             return
         }
         if self.is_falling_diagonal(index) {
@@ -332,11 +811,21 @@ impl SandBoard {
     }
 
     fn grow_grass(&mut self, index: usize) -> bool {
-        if self.granules[index].growth == 0 {
+        // grass draws its moisture up from whatever it's growing out of
+        let mut moisture = 0;
+        for x_relative in [0, -1, 1] {
+            if let Some(below_index) = self.get_relative_index(index, x_relative, 1) {
+                let below = self.granules[below_index];
+                if matches!(below.element_type, ElementType::Dirt | ElementType::Sand | ElementType::Grass) {
+                    moisture = moisture.max(below.moisture);
+                }
+            }
+        }
+        self.granules[index].moisture = moisture;
+        if self.granules[index].growth == 0 || moisture < GRASS_GROWTH_MOISTURE {
             return false
         }
-        let mut rng = rand::thread_rng();
-        let rng_val: f64 = rng.gen(); 
+        let rng_val: f64 = self.rng.gen(); 
         let mut directions = Vec::new();
         
         directions.push(self.get_relative_index(index, 0, -1));
@@ -348,17 +837,17 @@ impl SandBoard {
 
         if rng_val > 0.2 {
             let starting = directions.remove(0);
-            directions.shuffle(&mut rng);
+            directions.shuffle(&mut self.rng);
             directions.insert(0, starting);
         }
         else if rng_val > 0.1 {
             let starting = directions.remove(1);
-            directions.shuffle(&mut rng);
+            directions.shuffle(&mut self.rng);
             directions.insert(0, starting);
         }
         else {
             let starting = directions.remove(2);
-            directions.shuffle(&mut rng);
+            directions.shuffle(&mut self.rng);
             directions.insert(0, starting);
         }
 
@@ -380,7 +869,7 @@ impl SandBoard {
                     self.granules[direction.unwrap()].update_toggle = self.update_cycle;
                     self.granules[index].update_toggle = self.update_cycle;
 
-                    let new_rng: f64 = rng.gen();
+                    let new_rng: f64 = self.rng.gen();
                     //if new_rng < 0.25 {
                     //    return true;
                     //}
@@ -393,8 +882,7 @@ impl SandBoard {
 
 
     fn update_kelp(&mut self, index: usize) {
-        let mut rng = rand::thread_rng();
-        let rng_val: f64 = rng.gen(); 
+        let rng_val: f64 = self.rng.gen(); 
         let growth_chance = self.granules[index].hunger as f64 / 100.0;
         if rng_val < (growth_chance) {
             self.grow_kelp(index);
@@ -406,8 +894,7 @@ impl SandBoard {
         if self.granules[index].growth == 0 {
             return false
         }
-        let mut rng = rand::thread_rng();
-        let rng_val: f64 = rng.gen(); 
+        let rng_val: f64 = self.rng.gen(); 
         let mut directions = Vec::new();
         
         directions.push(self.get_relative_index(index, 0, -1));
@@ -419,17 +906,17 @@ impl SandBoard {
 
         if rng_val > 0.2 {
             let starting = directions.remove(0);
-            directions.shuffle(&mut rng);
+            directions.shuffle(&mut self.rng);
             directions.insert(0, starting);
         }
         else if rng_val > 0.1 {
             let starting = directions.remove(1);
-            directions.shuffle(&mut rng);
+            directions.shuffle(&mut self.rng);
             directions.insert(0, starting);
         }
         else {
             let starting = directions.remove(2);
-            directions.shuffle(&mut rng);
+            directions.shuffle(&mut self.rng);
             directions.insert(0, starting);
         }
 
@@ -447,7 +934,7 @@ impl SandBoard {
             if direction.is_some() {
                 if self.granules[direction.unwrap()].element_type == ElementType::Water {
                     self.granules[direction.unwrap()].element_type = ElementType::Kelp;
-                    let mut growth_penalty = rng.gen_range(1..3);
+                    let mut growth_penalty = self.rng.gen_range(1..3);
                     if growth_penalty > self.granules[index].growth {
                         growth_penalty = self.granules[index].growth;
                     }
@@ -456,7 +943,7 @@ impl SandBoard {
                     self.granules[direction.unwrap()].update_toggle = self.update_cycle;
                     self.granules[index].update_toggle = self.update_cycle;
                     
-                    let new_rng: f64 = rng.gen();
+                    let new_rng: f64 = self.rng.gen();
                     if new_rng < 0.25 {
                         return true;
                     }
@@ -593,8 +1080,7 @@ impl SandBoard {
             }
             
         }
-        else if !self.is_floor {
-            self.granules[index].element_type = ElementType::Empty;
+        else if self.is_leaving_board(index, 0, 1) {
             return true;
         }
         return false
@@ -602,8 +1088,7 @@ impl SandBoard {
 
     fn is_falling_down(&mut self, index: usize) -> bool {
         if let Some(below_index) = self.get_relative_index(index, 0, 1) {
-            if self.granules[below_index].element_type == ElementType::Water || 
-                self.granules[below_index].element_type == ElementType::Empty {
+            if self.can_sink_into(index, below_index) {
                     let granule_below = self.granules[below_index];
                     self.granules[below_index] = self.granules[index];
                     self.granules[index] = granule_below;
@@ -617,8 +1102,7 @@ impl SandBoard {
             }
             
         }
-        else if !self.is_floor {
-            self.granules[index].element_type = ElementType::Empty;
+        else if self.is_leaving_board(index, 0, 1) {
             return true;
         }
 
@@ -626,7 +1110,6 @@ impl SandBoard {
     }
 
     fn update_egg(&mut self, index: usize) {
-        let mut rng = rand::thread_rng();
 
         if self.is_falling_down(index) {
             return
@@ -637,9 +1120,10 @@ impl SandBoard {
 
         let rough_hatch_time_seconds = 1.0;
         let probability =  1.0 / (rough_hatch_time_seconds * 35.0);
-        if rng.gen::<f64>() < probability {
+        if self.rng.gen::<f64>() < probability {
+            // anything deeper than MAX_REACH counts as open water
             let mut depth = 20;
-            for i in 1..self.height {
+            for i in 1..=MAX_REACH {
                 let y = -1 * (i as i64);
                 if let Some(depth_check_index) = self.get_relative_index(index, 0, y) {
                     if self.granules[depth_check_index].element_type != ElementType::Water &&
@@ -658,11 +1142,22 @@ impl SandBoard {
                 }
             }
             if depth < 20 {
+                // shallow water, or land if there's no water above at all
+                if depth > 1 {
+                    self.granules[index].element_type = ElementType::Tadpole;
+                    self.granules[index].moisture = u8::MAX;
+                }
+                else {
+                    self.granules[index].element_type = ElementType::Isopod;
+                }
+                self.granules[index].growth = 0;
+                self.granules[index].hunger = 0;
+                self.granules[index].direction = self.rng.gen_bool(0.5);
                 return;
             }
             self.granules[index].element_type = ElementType::Minnow;
-            self.granules[index].moisture = rng.gen_range(3..(depth - 3) as u8);
-            if rng.gen::<f64>() < 0.5 {
+            self.granules[index].moisture = self.rng.gen_range(3..(depth - 3) as u8);
+            if self.rng.gen::<f64>() < 0.5 {
                 self.granules[index].direction = true;
             }
             else {
@@ -673,6 +1168,11 @@ impl SandBoard {
         
     }
 
+    // Minnows swim back and forth, rising now and then while moisture lasts
+    // (it counts how far up they can still go). They get hungrier as they go
+    // (hunger), and a hungry minnow turns towards kelp it can see, or dives
+    // for it if it can't. Enough meals (growth) and it lays an egg, too long
+    // without and it starves, sinking as detritus.
     fn update_minnow(&mut self, index: usize) {
 
         if self.is_falling_down_air(index) {
@@ -682,21 +1182,59 @@ impl SandBoard {
             return
         }
 
-        let mut rng = rand::thread_rng();
+        if self.rng.gen_bool(MINNOW_HUNGER_CHANCE) {
+            self.granules[index].hunger = self.granules[index].hunger.saturating_add(1);
+            if self.granules[index].hunger == u8::MAX {
+                let (x, y) = self.get_coordinates_from_index(index);
+                self.update_granule(x, y, ElementType::Detritus);
+                return
+            }
+        }
+
+        for (x_relative, y_relative) in NEIGHBOURS {
+            let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            if self.granules[neighbour_index].element_type != ElementType::Kelp {
+                continue
+            }
+            self.granules[neighbour_index] = self.new_element(ElementType::Water);
+            self.granules[neighbour_index].update_toggle = self.update_cycle;
+            self.granules[index].hunger = self.granules[index].hunger.saturating_sub(MINNOW_MEAL);
+            self.granules[index].growth = self.granules[index].growth.saturating_add(1);
+            return
+        }
+
+        let hungry = self.granules[index].hunger > MINNOW_HUNGRY;
+        if self.granules[index].growth >= MINNOW_BROOD_MEALS && !hungry && self.lay_egg(index) {
+            self.granules[index].growth = 0;
+        }
+        let mut kelp_in_sight = false;
+        if hungry {
+            if let Some(direction) = self.nearest_direction(index, ElementType::Kelp) {
+                self.granules[index].direction = direction;
+                kelp_in_sight = true;
+            }
+        }
+
         let mut direction = 1;
         if !self.granules[index].direction {
             direction = -1;
         }
         let mut y = 0;
-        if self.granules[index].moisture > 0 && rng.gen::<f64>() < 0.3 {
+        if hungry && !kelp_in_sight && self.rng.gen_bool(MINNOW_DIVE_CHANCE) {
+            // kelp grows up from the bottom
+            y = -1;
+        }
+        else if self.granules[index].moisture > 0 && self.rng.gen::<f64>() < 0.3 {
             y = 1;
-            self.granules[index].moisture -= 1;
         }
         if let Some(new_index) = self.get_relative_index(index, direction, -y) {
             if self.granules[new_index].element_type == ElementType::Water {
                 let temp = self.granules[new_index];
                 self.granules[new_index] = self.granules[index];
                 self.granules[index] = temp;
+                self.granules[new_index].moisture = self.granules[new_index].moisture.saturating_add_signed(-y as i8);
             }
             else {
                 self.granules[index].switch_direction()
@@ -709,6 +1247,392 @@ impl SandBoard {
     }
     
 
+    // Tadpoles swim back and forth, eating any kelp they brush past. Enough
+    // meals (counted in growth) turn a tadpole into a frog. Going too long
+    // without food (hunger) or out of water (moisture) kills it.
+    fn update_tadpole(&mut self, index: usize) {
+        if self.is_falling_down_air(index) {
+            return
+        }
+        let (x, y) = self.get_coordinates_from_index(index);
+
+        if !self.is_touching(index, ElementType::Water) {
+            self.granules[index].moisture = self.granules[index].moisture.saturating_sub(TADPOLE_DRYING);
+            if self.granules[index].moisture == 0 {
+                self.update_granule(x, y, ElementType::Empty);
+            }
+            return
+        }
+        self.granules[index].moisture = u8::MAX;
+
+        if self.rng.gen_bool(TADPOLE_HUNGER_CHANCE) {
+            self.granules[index].hunger = self.granules[index].hunger.saturating_add(1);
+            if self.granules[index].hunger == u8::MAX {
+                self.update_granule(x, y, ElementType::Water);
+                return
+            }
+        }
+
+        for (x_relative, y_relative) in NEIGHBOURS {
+            let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            if self.granules[neighbour_index].element_type != ElementType::Kelp {
+                continue
+            }
+            self.granules[neighbour_index] = self.new_element(ElementType::Water);
+            self.granules[neighbour_index].update_toggle = self.update_cycle;
+            self.granules[index].hunger = 0;
+            self.granules[index].growth = self.granules[index].growth.saturating_add(1);
+            if self.granules[index].growth >= TADPOLE_MEALS {
+                self.granules[index].element_type = ElementType::Frog;
+                self.granules[index].growth = 0;
+            }
+            return
+        }
+
+        let x_relative = if self.granules[index].direction { 1 } else { -1 };
+        let mut y_relative = 0;
+        if self.rng.gen_bool(TADPOLE_TURN_CHANCE) {
+            y_relative = if self.rng.gen_bool(0.5) { 1 } else { -1 };
+        }
+        match self.get_relative_index(index, x_relative, y_relative) {
+            Some(new_index) if self.granules[new_index].element_type == ElementType::Water => {
+                self.granules.swap(index, new_index);
+                self.granules[new_index].update_toggle = self.update_cycle;
+            },
+            _ => self.granules[index].switch_direction(),
+        }
+    }
+
+    // Frogs hop around, in water or out of it. On land they dry out
+    // (moisture), and a thirsty frog hops towards any water it can see. A
+    // frog that stays in the water long enough (growth) lays an egg there.
+    fn update_frog(&mut self, index: usize) {
+        if self.is_falling_down_air(index) {
+            return
+        }
+
+        if self.is_touching(index, ElementType::Water) {
+            self.granules[index].moisture = u8::MAX;
+            if self.rng.gen_bool(FROG_BREEDING_CHANCE) {
+                self.granules[index].growth = self.granules[index].growth.saturating_add(1);
+            }
+            if self.granules[index].growth == u8::MAX && self.lay_egg(index) {
+                self.granules[index].growth = 0;
+            }
+        }
+        else {
+            if self.rng.gen_bool(FROG_DRYING_CHANCE) {
+                self.granules[index].moisture = self.granules[index].moisture.saturating_sub(1);
+                if self.granules[index].moisture == 0 {
+                    let (x, y) = self.get_coordinates_from_index(index);
+                    self.update_granule(x, y, ElementType::Empty);
+                    return
+                }
+            }
+            if self.granules[index].moisture < FROG_THIRSTY {
+                if let Some(direction) = self.nearest_direction(index, ElementType::Water) {
+                    self.granules[index].direction = direction;
+                }
+            }
+        }
+
+        if self.rng.gen_bool(FROG_HOP_CHANCE) {
+            let x_relative = if self.granules[index].direction { FROG_HOP.0 } else { -FROG_HOP.0 };
+            match self.jump_end(index, x_relative, -FROG_HOP.1, |element_type| matches!(element_type, ElementType::Empty | ElementType::Water)) {
+                Some(new_index) => {
+                    self.granules.swap(index, new_index);
+                    self.granules[new_index].update_toggle = self.update_cycle;
+                },
+                _ => self.granules[index].switch_direction(),
+            }
+        }
+    }
+
+    // Isopods crawl over the ground, climbing small steps and now and then
+    // burrowing through dirt. They get hungrier as they go (hunger), and a
+    // hungry isopod heads for any detritus it can see. Once it's eaten a few
+    // times (growth) and isn't hungry it lays an egg.
+    fn update_isopod(&mut self, index: usize) {
+        if self.is_falling_down(index) {
+            return
+        }
+        let (x, y) = self.get_coordinates_from_index(index);
+
+        if self.rng.gen_bool(ISOPOD_HUNGER_CHANCE) {
+            self.granules[index].hunger = self.granules[index].hunger.saturating_add(1);
+            if self.granules[index].hunger == u8::MAX {
+                self.update_granule(x, y, ElementType::Empty);
+                return
+            }
+        }
+
+        for (x_relative, y_relative) in NEIGHBOURS {
+            let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            if self.granules[neighbour_index].element_type != ElementType::Detritus {
+                continue
+            }
+            self.granules[neighbour_index] = self.new_element(ElementType::Empty);
+            self.granules[index].hunger = self.granules[index].hunger.saturating_sub(ISOPOD_MEAL);
+            self.granules[index].growth = self.granules[index].growth.saturating_add(1);
+            return
+        }
+
+        if self.granules[index].growth >= ISOPOD_BROOD_MEALS && self.granules[index].hunger < ISOPOD_HUNGRY {
+            let behind = if self.granules[index].direction { -1 } else { 1 };
+            if let Some(behind_index) = self.get_relative_index(index, behind, 0) {
+                if self.granules[behind_index].element_type == ElementType::Empty {
+                    self.granules[behind_index] = self.new_element(ElementType::Egg);
+                    self.granules[behind_index].update_toggle = self.update_cycle;
+                    self.granules[index].growth = 0;
+                }
+            }
+        }
+        else if self.granules[index].hunger > ISOPOD_HUNGRY {
+            if let Some(direction) = self.nearest_direction(index, ElementType::Detritus) {
+                self.granules[index].direction = direction;
+            }
+        }
+
+        if self.rng.gen_bool(ISOPOD_CRAWL_CHANCE) {
+            self.crawl(index, ISOPOD_STEP, ISOPOD_BURROW_CHANCE);
+        }
+    }
+
+    // Snails creep along whatever they're sitting on, underwater or on land
+    // as long as it's damp (moisture), grazing moss and kelp as they go. A
+    // hungry snail heads for any it can see, and a well fed one (growth)
+    // leaves a young snail behind it.
+    fn update_snail(&mut self, index: usize) {
+        if self.is_falling_down(index) {
+            return
+        }
+        let (x, y) = self.get_coordinates_from_index(index);
+
+        if self.is_damp(index) {
+            self.granules[index].moisture = u8::MAX;
+        }
+        else if self.rng.gen_bool(SNAIL_DRYING_CHANCE) {
+            self.granules[index].moisture = self.granules[index].moisture.saturating_sub(1);
+            if self.granules[index].moisture == 0 {
+                self.update_granule(x, y, ElementType::Empty);
+                return
+            }
+        }
+
+        if self.rng.gen_bool(SNAIL_HUNGER_CHANCE) {
+            self.granules[index].hunger = self.granules[index].hunger.saturating_add(1);
+            if self.granules[index].hunger == u8::MAX {
+                self.update_granule(x, y, ElementType::Empty);
+                return
+            }
+        }
+
+        if self.rng.gen_bool(SNAIL_GRAZING_CHANCE) {
+            for (x_relative, y_relative) in NEIGHBOURS {
+                let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                    continue
+                };
+                // kelp only grows in water, so what's left where it was eaten is water
+                let eaten = match self.granules[neighbour_index].element_type {
+                    ElementType::Moss => ElementType::Empty,
+                    ElementType::Kelp => ElementType::Water,
+                    _ => continue,
+                };
+                self.granules[neighbour_index] = self.new_element(eaten);
+                self.granules[neighbour_index].update_toggle = self.update_cycle;
+                self.granules[index].hunger = self.granules[index].hunger.saturating_sub(SNAIL_MEAL);
+                self.granules[index].growth = self.granules[index].growth.saturating_add(1);
+                return
+            }
+        }
+
+        if self.granules[index].growth >= SNAIL_BROOD_MEALS && self.granules[index].hunger < SNAIL_HUNGRY {
+            let behind = if self.granules[index].direction { -1 } else { 1 };
+            if let Some(behind_index) = self.get_relative_index(index, behind, 0) {
+                if self.is_crawlable(behind_index) {
+                    self.granules[behind_index] = self.new_element(ElementType::Snail);
+                    self.granules[behind_index].direction = !self.granules[index].direction;
+                    self.granules[behind_index].update_toggle = self.update_cycle;
+                    self.granules[index].growth = 0;
+                }
+            }
+        }
+        else if self.granules[index].hunger > SNAIL_HUNGRY {
+            let food = self.nearest_direction(index, ElementType::Moss)
+                .or_else(|| self.nearest_direction(index, ElementType::Kelp));
+            if let Some(direction) = food {
+                self.granules[index].direction = direction;
+            }
+        }
+
+        if self.rng.gen_bool(SNAIL_CRAWL_CHANCE) {
+            self.crawl(index, SNAIL_STEP, 0.0);
+        }
+    }
+
+    // Springtails live in damp dirt, wriggling through it and jumping about
+    // at random over the top of it. They eat detritus and moss, and breed
+    // quickly when there's plenty (growth), but dry out (moisture) within a
+    // few seconds of leaving the damp and starve soon after the food runs out.
+    fn update_springtail(&mut self, index: usize) {
+        // light enough to float on water
+        if self.is_falling_down_air(index) {
+            return
+        }
+        let (x, y) = self.get_coordinates_from_index(index);
+
+        if self.is_damp(index) {
+            self.granules[index].moisture = u8::MAX;
+        }
+        else {
+            self.granules[index].moisture = self.granules[index].moisture.saturating_sub(SPRINGTAIL_DRYING);
+            if self.granules[index].moisture == 0 {
+                self.update_granule(x, y, ElementType::Empty);
+                return
+            }
+        }
+
+        if self.rng.gen_bool(SPRINGTAIL_HUNGER_CHANCE) {
+            self.granules[index].hunger = self.granules[index].hunger.saturating_add(1);
+            if self.granules[index].hunger == u8::MAX {
+                self.update_granule(x, y, ElementType::Empty);
+                return
+            }
+        }
+
+        for (x_relative, y_relative) in NEIGHBOURS {
+            let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) else {
+                continue
+            };
+            if !matches!(self.granules[neighbour_index].element_type, ElementType::Detritus | ElementType::Moss) {
+                continue
+            }
+            if self.granules[index].growth >= SPRINGTAIL_BROOD_MEALS && self.granules[index].hunger < SPRINGTAIL_HUNGRY {
+                // the young one takes the place of the meal
+                self.granules[neighbour_index] = self.new_element(ElementType::SpringTail);
+                self.granules[neighbour_index].direction = self.rng.gen_bool(0.5);
+                self.granules[index].growth = 0;
+            }
+            else {
+                self.granules[neighbour_index] = self.new_element(ElementType::Empty);
+                self.granules[index].growth = self.granules[index].growth.saturating_add(1);
+            }
+            self.granules[neighbour_index].update_toggle = self.update_cycle;
+            self.granules[index].hunger = self.granules[index].hunger.saturating_sub(SPRINGTAIL_MEAL);
+            return
+        }
+
+        if self.rng.gen_bool(SPRINGTAIL_BURROW_CHANCE) {
+            let (x_relative, y_relative) = NEIGHBOURS[self.rng.gen_range(0..NEIGHBOURS.len())];
+            if let Some(new_index) = self.get_relative_index(index, x_relative, y_relative) {
+                if self.granules[new_index].element_type == ElementType::Dirt && self.is_damp(new_index) {
+                    self.granules.swap(index, new_index);
+                    self.granules[new_index].update_toggle = self.update_cycle;
+                    return
+                }
+            }
+        }
+
+        if self.rng.gen_bool(SPRINGTAIL_JUMP_CHANCE) {
+            // a hungry springtail jumps towards food if it can see any,
+            // otherwise it goes whichever way
+            let food = if self.granules[index].hunger > SPRINGTAIL_HUNGRY {
+                self.nearest_direction(index, ElementType::Detritus)
+            }
+            else {
+                None
+            };
+            self.granules[index].direction = food.unwrap_or_else(|| self.rng.gen_bool(0.5));
+            let distance = self.rng.gen_range(1..=SPRINGTAIL_JUMP.0);
+            let x_relative = if self.granules[index].direction { distance } else { -distance };
+            let y_relative = -self.rng.gen_range(0..=SPRINGTAIL_JUMP.1);
+            if let Some(new_index) = self.jump_end(index, x_relative, y_relative, |element_type| element_type == ElementType::Empty) {
+                self.granules.swap(index, new_index);
+                self.granules[new_index].update_toggle = self.update_cycle;
+            }
+        }
+    }
+
+    // Whether there's water, moss or damp soil around the cell to keep a
+    // small creature from drying out.
+    fn is_damp(&self, index: usize) -> bool {
+        self.get_surrounding_from_index(index).iter().flatten().any(|granule| match granule.element_type {
+            ElementType::Water | ElementType::Moss => true,
+            ElementType::Dirt | ElementType::Sand => granule.moisture >= DAMP_SOIL_MOISTURE,
+            _ => false,
+        })
+    }
+
+    // Steps the creature one cell the way it's facing, climbing up to
+    // `max_step` cells and sometimes digging into dirt in the way. It turns
+    // around when it can't go on.
+    fn crawl(&mut self, index: usize, max_step: i64, burrow_chance: f64) {
+        let x_relative = if self.granules[index].direction { 1 } else { -1 };
+        for step in 0..=max_step {
+            // climbing needs headroom over the creature as well as in front
+            if step > 0 {
+                match self.get_relative_index(index, 0, -step) {
+                    Some(above_index) if self.is_crawlable(above_index) => {},
+                    _ => break,
+                }
+            }
+            let Some(new_index) = self.get_relative_index(index, x_relative, -step) else {
+                break
+            };
+            if self.is_crawlable(new_index) {
+                self.granules.swap(index, new_index);
+                self.granules[new_index].update_toggle = self.update_cycle;
+                return
+            }
+            if step == 0 && self.granules[new_index].element_type == ElementType::Dirt && self.rng.gen_bool(burrow_chance) {
+                self.granules.swap(index, new_index);
+                self.granules[new_index].update_toggle = self.update_cycle;
+                return
+            }
+        }
+        self.granules[index].switch_direction();
+    }
+
+    fn is_crawlable(&self, index: usize) -> bool {
+        matches!(self.granules[index].element_type, ElementType::Empty | ElementType::Water)
+    }
+
+    // Puts an egg in water next to the frog, if there's any.
+    fn lay_egg(&mut self, index: usize) -> bool {
+        for (x_relative, y_relative) in NEIGHBOURS {
+            if let Some(neighbour_index) = self.get_relative_index(index, x_relative, y_relative) {
+                if self.granules[neighbour_index].element_type == ElementType::Water {
+                    self.granules[neighbour_index] = self.new_element(ElementType::Egg);
+                    self.granules[neighbour_index].update_toggle = self.update_cycle;
+                    return true
+                }
+            }
+        }
+        false
+    }
+
+    // Which way the nearest `element_type` is along the ground, looking as
+    // far as MAX_REACH either way: true for right, like direction. None if
+    // there's none in sight.
+    fn nearest_direction(&mut self, index: usize, element_type: ElementType) -> Option<bool> {
+        for distance in 1..=MAX_REACH as i64 {
+            for (x_relative, direction) in [(distance, true), (-distance, false)] {
+                for y_relative in [0, 1] {
+                    if let Some(check_index) = self.get_relative_index(index, x_relative, y_relative) {
+                        if self.granules[check_index].element_type == element_type {
+                            return Some(direction)
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
     /* 
     fn update_minnow(&mut self, index: usize) {
         // height is distance from bottom? top?
@@ -0,0 +1,96 @@
+// The water cycle. Open water slowly evaporates into clouds, clouds drift
+// and gather into bigger ones, and once a cloud holds enough moisture it
+// rains the water back down (see update_cloud).
+//
+// Evaporation happens here rather than in the water's own update, because a
+// settled lake is exactly what lets its chunks go to sleep. Instead each tick
+// a few random columns are checked, and if the first thing under the open
+// sky is water it may evaporate.
+
+use rand::Rng;
+
+use super::SandBoard;
+use super::elements::{ElementType, AMBIENT_TEMPERATURE};
+
+// the chance each tick that a column's water surface evaporates, at the
+// ambient temperature. It goes up in proportion to the temperature (in
+// degrees) up to MAX_WARMTH times as fast, and water at or below freezing
+// doesn't evaporate.
+const EVAPORATION_CHANCE: f64 = 1.0 / 3000.0;
+const MAX_WARMTH: f64 = 4.0;
+
+// How much moisture a cloud gets from one granule of water, and gives up to
+// make one raindrop, so the water cycle neither makes nor loses water.
+pub const RAINDROP_MOISTURE: u8 = 64;
+// clouds rain once they hold this much moisture
+pub const CLOUD_SATURATION: u8 = 192;
+
+
+impl SandBoard {
+    pub(super) fn evaporate(&mut self) {
+        for x in 0..self.width {
+            if !self.rng.gen_bool(EVAPORATION_CHANCE * MAX_WARMTH) {
+                continue
+            }
+            let Some(y) = (0..self.height).find(|y| self.granules[self.get_index_from_coordinates(x, *y)].element_type != ElementType::Empty) else {
+                continue
+            };
+            let index = self.get_index_from_coordinates(x, y);
+            let granule = self.granules[index];
+            if granule.element_type != ElementType::Water || granule.temperature <= 0 {
+                continue
+            }
+            let warmth = (granule.temperature as f64 / AMBIENT_TEMPERATURE as f64).min(MAX_WARMTH);
+            if !self.rng.gen_bool(warmth / MAX_WARMTH) {
+                continue
+            }
+            self.granules[index] = self.new_element(ElementType::Cloud);
+            self.granules[index].moisture = RAINDROP_MOISTURE;
+            self.granules[index].temperature = granule.temperature;
+            self.wake_region(x, y, x, y);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod weather_test {
+    use super::*;
+    use super::super::elements::degrees;
+
+    fn total_water(sand_board: &SandBoard) -> usize {
+        sand_board.granules.iter().map(|granule| match granule.element_type {
+            ElementType::Water => RAINDROP_MOISTURE as usize,
+            ElementType::Cloud => granule.moisture as usize,
+            _ => 0,
+        }).sum()
+    }
+
+    #[test]
+    fn water_cycle_keeps_its_water() {
+        let mut sand_board = SandBoard::with_seed(64, 48, 21);
+        for y in 38..48 {
+            for x in 0..64 {
+                sand_board.add_granule(x, y, ElementType::Water);
+            }
+        }
+        // warm water so it doesn't take all day
+        sand_board.set_temperature_region(0, 38, 64, 10, degrees(80));
+        let water_before = total_water(&sand_board);
+
+        let mut clouds_seen = false;
+        let mut rain_seen = false;
+        for tick in 0..1000 {
+            sand_board.tick();
+            if tick % 10 != 0 {
+                continue
+            }
+            for (index, granule) in sand_board.granules.iter().enumerate() {
+                clouds_seen |= granule.element_type == ElementType::Cloud;
+                rain_seen |= granule.element_type == ElementType::Water && index < 30 * 64;
+            }
+        }
+        assert!(clouds_seen && rain_seen);
+        assert_eq!(total_water(&sand_board), water_before);
+    }
+}
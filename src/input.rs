@@ -18,6 +18,12 @@ pub struct InputBuffer {
     pub is_r_pressed: bool,
     pub is_z_pressed: bool,
     pub is_x_pressed: bool,
+    pub is_u_pressed: bool,
+    pub is_y_pressed: bool,
+    pub is_f5_pressed: bool,
+    pub is_f9_pressed: bool,
+    pub is_f6_pressed: bool,
+    pub is_f3_pressed: bool,
 }
 
 
@@ -45,6 +51,12 @@ impl InputBuffer {
             is_r_pressed: false,
             is_z_pressed: false,
             is_x_pressed: false,
+            is_u_pressed: false,
+            is_y_pressed: false,
+            is_f5_pressed: false,
+            is_f9_pressed: false,
+            is_f6_pressed: false,
+            is_f3_pressed: false,
         }
     }
 
@@ -66,6 +78,12 @@ impl InputBuffer {
         self.is_r_pressed = false;
         self.is_z_pressed = false;
         self.is_x_pressed = false;
+        self.is_u_pressed = false;
+        self.is_y_pressed = false;
+        self.is_f5_pressed = false;
+        self.is_f9_pressed = false;
+        self.is_f6_pressed = false;
+        self.is_f3_pressed = false;
     }
 
 
@@ -226,6 +244,28 @@ impl InputBuffer {
                 self.is_x_pressed = true;
                 return true
             },
+            WindowEvent::KeyboardInput { event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyU),
+                    ..
+                },
+            .. 
+            } => {
+                self.is_u_pressed = true;
+                return true
+            },
+            WindowEvent::KeyboardInput { event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyY),
+                    ..
+                },
+            .. 
+            } => {
+                self.is_y_pressed = true;
+                return true
+            },
             WindowEvent::KeyboardInput { event:
                 KeyEvent {
                     state: ElementState::Pressed,
@@ -237,6 +277,50 @@ impl InputBuffer {
                 self.is_down_pressed = true;
                 return true
             },
+            WindowEvent::KeyboardInput { event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::F5),
+                    ..
+                },
+            .. 
+            } => {
+                self.is_f5_pressed = true;
+                return true
+            },
+            WindowEvent::KeyboardInput { event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::F6),
+                    ..
+                },
+            .. 
+            } => {
+                self.is_f6_pressed = true;
+                return true
+            },
+            WindowEvent::KeyboardInput { event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::F9),
+                    ..
+                },
+            .. 
+            } => {
+                self.is_f9_pressed = true;
+                return true
+            },
+            WindowEvent::KeyboardInput { event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::F3),
+                    ..
+                },
+            .. 
+            } => {
+                self.is_f3_pressed = true;
+                return true
+            },
             WindowEvent::MouseInput {state: ElementState::Pressed, button: MouseButton::Left, ..} => {
                 self.is_left_clicked = true;
                 return true
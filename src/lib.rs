@@ -3,6 +3,7 @@ use std::{collections::HashMap, hash::Hash};
 use std::sync::Arc;
 
 pub mod test_texture;
+pub mod falling_sand;
 use crate::test_texture::TestTexture;
 
 use wgpu::Texture;
@@ -13,11 +13,13 @@ use crate::input::*;
 mod debug_tools;
 use debug_tools::*;
 
-mod falling_sand;
-use falling_sand::*;
+use sand_game::falling_sand::*;
 
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::falling_sand::{elements::ElementType, screen_texture::*};
+use sand_game::falling_sand::{elements::{ElementType, TEMPERATURE_SCALE}, screen_texture::*, save_file::SaveError};
+use sand_game::falling_sand::element_registry::ElementRegistry;
+use sand_game::falling_sand::edges::EdgeMode;
+use sand_game::falling_sand::replay::{Action, Recording, ReplayError};
 
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize}, event::*, event_loop::EventLoop, keyboard::{KeyCode, PhysicalKey}, window::{Window, WindowBuilder}
@@ -30,8 +32,14 @@ struct ResizeEvent {
     send_event: bool,
 }
 
-const ELEMENT_COLORS: [[u8; 4]; 7] = [COLORS_BACKGROUND, COLORS_YELLOW, COLORS_BROWN, COLORS_BLUE, COLORS_LIGHT_GREEN, COLORS_DARK_ORANGE, COLORS_GREY];
-const ELEMENT_LIST: [ElementType; 7] = [ElementType::Empty, ElementType::Sand, ElementType::Dirt, ElementType::Water, ElementType::Seed, ElementType::Egg, ElementType::Wall];
+const QUICK_SAVE_PATH: &str = "quicksave.sand";
+// where F6 writes the session it recorded, see replay.rs
+const RECORDING_PATH: &str = "session.replay";
+// loaded at startup if it exists, see the format in element_registry.rs
+const ELEMENT_DEFINITIONS_PATH: &str = "assets/elements.txt";
+
+// the built in elements in the selection bar, custom elements are added after them
+const ELEMENT_LIST: [ElementType; 17] = [ElementType::Empty, ElementType::Sand, ElementType::Dirt, ElementType::Water, ElementType::Seed, ElementType::Egg, ElementType::Wall, ElementType::Moss, ElementType::Fire, ElementType::Cloud, ElementType::Snail, ElementType::SpringTail, ElementType::Ice, ElementType::Snow, ElementType::Steam, ElementType::Emitter, ElementType::Drain];
 
 struct SandPen {
     size: usize,
@@ -39,33 +47,68 @@ struct SandPen {
     max_size: usize,
     granule_rate: usize,
     pub element_index: usize,
+    elements: Vec<ElementType>,
+    colors: Vec<[u8; 4]>,
+    // what emitters get painted putting out, the last thing picked before them
+    emits: ElementType,
 }
 
 impl SandPen {
-    pub fn new(size: usize, min_size: usize, max_size: usize, start_type: ElementType) -> Self {
-
-        let mut element_index = 0;
-        for i in 0..ELEMENT_LIST.len() {
-            if start_type == ELEMENT_LIST[i] {
-                element_index = i;
+    pub fn new(size: usize, min_size: usize, max_size: usize, start_type: ElementType, registry: &ElementRegistry) -> Self {
+        let mut elements = ELEMENT_LIST.to_vec();
+        elements.extend(registry.custom_elements());
+
+        let mut colors = Vec::new();
+        for element_type in elements.iter() {
+            if *element_type == ElementType::Empty {
+                // the eraser swatch has to stand out from the bar behind it
+                colors.push(COLORS_BACKGROUND);
+            }
+            else {
+                colors.push(registry.color(*element_type));
             }
         }
 
-        Self {
+        let mut sand_pen = Self {
             size,
             min_size,
             max_size,
             granule_rate: 1,
-            element_index,
-        }
+            element_index: 0,
+            elements,
+            colors,
+            emits: ElementType::Water,
+        };
+        sand_pen.set_element(start_type);
+        sand_pen
     }
     pub fn set_element(&mut self, element: ElementType) {
-        for i in 0..ELEMENT_LIST.len() {
-            if element == ELEMENT_LIST[i] {
+        for i in 0..self.elements.len() {
+            if element == self.elements[i] {
                 self.element_index = i;
             }
         }
     }
+    pub fn element(&self) -> ElementType {
+        self.elements[self.element_index]
+    }
+    fn remember_emitted(&mut self) {
+        let element = self.element();
+        if !matches!(element, ElementType::Empty | ElementType::Emitter | ElementType::Drain) {
+            self.emits = element;
+        }
+    }
+}
+
+// Everything the game keeps between frames, apart from the window and input.
+struct GameState {
+    sand_board: SandBoard,
+    sand_pen: SandPen,
+    // ticking only when z is pressed
+    frame_mode: bool,
+    advance_frame: bool,
+    show_chunks: bool,
+    recording: Option<Recording>,
 }
 
 
@@ -112,25 +155,34 @@ fn main() {
 
     let mut average_fps = 0.0;
 
-    let mut is_in_frame_by_frame_mode = false;
-    let mut play_next_frame = false;
-
     let mut input: InputBuffer = InputBuffer::new();
 
+    let registry = Arc::new(load_element_definitions());
+
     let mut sand_board: SandBoard = SandBoard::new(200, 200);
+    sand_board.set_registry(registry.clone());
+    sand_board.set_threads(std::thread::available_parallelism().map_or(1, |threads| threads.get()));
     sand_board.boring_ocean();
 
     //sand_board.first_ten();
     //sand_board.middle();
 
-    let mut sand_pen = SandPen::new(4, 1, 20, ElementType::Sand);
+    let sand_pen = SandPen::new(4, 1, 20, ElementType::Sand, &registry);
+    let mut game = GameState {
+        sand_board,
+        sand_pen,
+        frame_mode: false,
+        advance_frame: false,
+        show_chunks: false,
+        recording: None,
+    };
 
     let mut timers = DebugTools::new();
     let fps_timer = timers.add_timer();
     timers.get_time_reset(fps_timer);
 
 
-    let mut sand_texture: ScreenTexture = sand_board.output_texture();
+    let mut sand_texture: ScreenTexture = game.sand_board.output_texture();
     let dimensions = [sand_texture.dimensions.0 as u32, sand_texture.dimensions.1 as u32];
 
     //let test_texture: TestTexture = TestTexture::new("happy-tree.png", &engine.device, &engine.queue, &engine.bind_group_layouts["texture"]);
@@ -180,7 +232,7 @@ fn main() {
                                 average_fps = (average_fps * fraction) + (fps * (1.0 - fraction)) ;
                                 let fps_string = format!("{:.1}", average_fps);
                                 
-                                update(&mut sand_board, &mut engine, &mut input, &mut sand_pen, &fps_string, &mut is_in_frame_by_frame_mode, &mut play_next_frame);
+                                update(&mut game, &mut engine, &mut input, &fps_string);
 
                                 match engine.render() {
                                     Ok(_) => {}
@@ -214,8 +266,10 @@ fn main() {
 }
 
 
-fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut InputBuffer, sand_pen: &mut SandPen, fps_string: &String, frame_mode: &mut bool, advance_frame: &mut bool) {
-    let mut added_granules = 0;
+fn update(game: &mut GameState, engine: &mut GraphicsEngine, input: &mut InputBuffer, fps_string: &String) {
+    let GameState { sand_board, sand_pen, frame_mode, advance_frame, show_chunks, recording } = game;
+    // everything done to the board goes through here, so it can be recorded
+    let mut actions = Vec::new();
 
     if input.is_up_pressed {
         sand_pen.size += 1;
@@ -252,36 +306,58 @@ fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut I
     }
     if input.is_q_pressed {
         sand_pen.element_index += 1;
-        if sand_pen.element_index >= ELEMENT_LIST.len() {
+        if sand_pen.element_index >= sand_pen.elements.len() {
             sand_pen.element_index = 0;
         }
     }
     if input.is_a_pressed {
         if sand_pen.element_index <= 0 {
-            sand_pen.element_index = ELEMENT_LIST.len() - 1;
+            sand_pen.element_index = sand_pen.elements.len() - 1;
         }
         else {
             sand_pen.element_index -= 1;
         }  
     }
 
+    if input.is_f6_pressed {
+        toggle_recording(sand_board, recording);
+    }
+
     if input.is_r_pressed {
-        sand_board.reset();
+        actions.push(Action::Reset);
+    }
+    if input.is_u_pressed {
+        actions.push(Action::Undo);
+    }
+    if input.is_y_pressed {
+        actions.push(Action::Redo);
+    }
+
+    if input.is_f5_pressed {
+        quick_save(sand_board);
+    }
+    if input.is_f9_pressed {
+        actions.extend(quick_load());
     }
 
+    sand_pen.remember_emitted();
+
     let mut starting_granules = sand_board.get_granule_count();
     if input.is_left_clicked {
         let (x, y) = get_granule_index(sand_board, engine, input);
         let mut size = sand_pen.size;
-        if ELEMENT_LIST[sand_pen.element_index] == ElementType::Seed {
+        if sand_pen.element() == ElementType::Seed {
             size = 1;
         }
         //println!("{} {}", x, y);
         if x > 0.0 && y > 0.0 {
-            added_granules = sand_board.add_granules(x as usize, y as usize, size, ELEMENT_LIST[sand_pen.element_index as usize]);
-
+            actions.push(Action::Paint { x: x as usize, y: y as usize, size, element: sand_pen.element(), emits: sand_pen.emits });
         }
     }
+    else {
+        // a stroke lasts as long as the button is held, and undoes as one
+        actions.push(Action::FinishEdit);
+    }
 
 
     
@@ -290,14 +366,32 @@ fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut I
 
      
     if input.is_space_pressed {
-        if sand_board.is_floor {
-            sand_board.is_floor = false;
-            //sand_board.is_floor = true;
+        let mut edges = sand_board.edges();
+        if edges.bottom == EdgeMode::Void {
+            edges.bottom = EdgeMode::Solid;
         }
         else {
-            sand_board.is_floor = true;
+            // a wrap has to come off both sides
+            if edges.bottom == EdgeMode::Wrap {
+                edges.top = EdgeMode::Solid;
+            }
+            edges.bottom = EdgeMode::Void;
+        }
+        actions.push(Action::SetEdges(edges));
+    }
+
+    for action in actions {
+        let is_load = matches!(action, Action::Load(_));
+        let result = match recording {
+            Some(recording) => recording.act(sand_board, action),
+            None => action.apply(sand_board),
+        };
+        match result {
+            Ok(_) if is_load => println!("loaded board from {}", QUICK_SAVE_PATH),
+            Ok(_) => {},
+            // only loading can fail
+            Err(e) => log::error!("couldn't load board from {}: {}", QUICK_SAVE_PATH, e),
         }
-           
     }
 
     if input.is_x_pressed {
@@ -311,20 +405,22 @@ fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut I
 
     }
 
+    if input.is_f3_pressed {
+        *show_chunks = !*show_chunks;
+    }
+
     if *frame_mode {
         if input.is_z_pressed {
             *advance_frame = true;
         }
     }
     
-    if *frame_mode {
-        if *advance_frame {
-            sand_board.tick();
-            *advance_frame = false;
+    if !*frame_mode || *advance_frame {
+        match recording {
+            Some(recording) => recording.tick(sand_board),
+            None => sand_board.tick(),
         }
-    }
-    else {
-        sand_board.tick();
+        *advance_frame = false;
     }
     
 
@@ -332,14 +428,21 @@ fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut I
     let granule_count_string = format!("{}", ending_granules);
 
     let mut sand_texture: ScreenTexture = sand_board.output_texture();
+    if *show_chunks {
+        draw_chunk_overlay(sand_board, &mut sand_texture);
+    }
     sand_texture.print(fps_string.to_string(), (0,0));
     sand_texture.print(granule_count_string.to_string(), (0,1));
+    if let Some(recording) = recording {
+        // ticks recorded so far
+        sand_texture.print(format!("{}", recording.ticks()), (0, 5));
+    }
 
     let pen_size_string = format!("{}", sand_pen.size);
     sand_texture.print(pen_size_string.to_string(), (0,2));
 
 
-     //println!("added: {} start: {} after_add: {} end: {} {} {}", added_granules, starting_granules, before_tick_granules, ending_granules, sand_board.left_sand, sand_board.right_sand);
+     //println!("start: {} after_add: {} end: {} {} {}", starting_granules, before_tick_granules, ending_granules, sand_board.left_sand, sand_board.right_sand);
 
     let (x, y) = get_granule_index(sand_board, engine, input);
         //println!("{} {}", x, y);
@@ -347,10 +450,16 @@ fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut I
         //sand_board.add_granule(x as usize, y as usize);
         let surrounding_data = sand_board.get_surrounding(x as usize, y as usize);
         sand_texture.encode_debug_info(surrounding_data);
+        if *show_chunks {
+            if let Some(temperature) = sand_board.get_temperature(x as usize, y as usize) {
+                let temperature_string = format!("{:.1}", temperature as f64 / TEMPERATURE_SCALE as f64);
+                sand_texture.print(temperature_string, (0, 4));
+            }
+        }
     }
 
     let ui_position = (30, 3);
-    draw_selection_bar(input.mouse_pos, input.is_left_clicked, sand_pen.element_index, ui_position, &mut sand_texture);
+    draw_selection_bar(input.mouse_pos, input.is_left_clicked, sand_pen.element_index, &sand_pen.colors, ui_position, &mut sand_texture);
 
     input.reset_input();
     sand_board.left_sand = 0; 
@@ -371,6 +480,83 @@ fn update(sand_board: &mut SandBoard, engine: &mut GraphicsEngine, input: &mut I
 }
 
 
+fn quick_save(sand_board: &SandBoard) {
+    let result = std::fs::File::create(QUICK_SAVE_PATH)
+        .map_err(SaveError::from)
+        .and_then(|file| sand_board.save(std::io::BufWriter::new(file)));
+    match result {
+        Ok(_) => println!("saved board to {}", QUICK_SAVE_PATH),
+        Err(e) => log::error!("couldn't save board to {}: {}", QUICK_SAVE_PATH, e),
+    }
+}
+
+// Reads the quick save, to be loaded as an action.
+fn quick_load() -> Option<Action> {
+    match std::fs::read(QUICK_SAVE_PATH) {
+        Ok(bytes) => Some(Action::Load(bytes)),
+        Err(e) => {
+            log::error!("couldn't load board from {}: {}", QUICK_SAVE_PATH, e);
+            None
+        },
+    }
+}
+
+// Starts recording the session, or stops and writes it to RECORDING_PATH.
+fn toggle_recording(sand_board: &mut SandBoard, recording: &mut Option<Recording>) {
+    match recording.take() {
+        None => match Recording::start(sand_board) {
+            Ok(started) => {
+                println!("recording");
+                *recording = Some(started);
+            },
+            Err(e) => log::error!("couldn't start recording: {}", e),
+        },
+        Some(finished) => {
+            finished.stop(sand_board);
+            let result = std::fs::File::create(RECORDING_PATH)
+                .map_err(ReplayError::from)
+                .and_then(|file| finished.save(std::io::BufWriter::new(file)));
+            match result {
+                Ok(_) => println!("saved {} ticks of recording to {}", finished.ticks(), RECORDING_PATH),
+                Err(e) => log::error!("couldn't save recording to {}: {}", RECORDING_PATH, e),
+            }
+        },
+    }
+}
+
+
+// Outlines the chunks that were updated last tick: red where something
+// moved, grey where the chunk was only checked.
+fn draw_chunk_overlay(sand_board: &SandBoard, sand_texture: &mut ScreenTexture) {
+    for chunk in sand_board.chunk_activity() {
+        if !chunk.is_awake {
+            continue
+        }
+        let color = if chunk.has_changed { COLORS_DARK_RED } else { COLORS_GREY };
+        sand_texture.print_empty_rect((chunk.x, chunk.y), (chunk.width - 1, chunk.height - 1), color);
+    }
+    let awake_string = format!("{}", sand_board.awake_chunk_count());
+    sand_texture.print(awake_string, (0, 3));
+}
+
+
+fn load_element_definitions() -> ElementRegistry {
+    if !std::path::Path::new(ELEMENT_DEFINITIONS_PATH).exists() {
+        return ElementRegistry::default()
+    }
+    match ElementRegistry::from_file(ELEMENT_DEFINITIONS_PATH) {
+        Ok(registry) => {
+            println!("loaded element definitions from {}", ELEMENT_DEFINITIONS_PATH);
+            return registry
+        },
+        Err(e) => {
+            log::error!("couldn't load element definitions from {}: {}", ELEMENT_DEFINITIONS_PATH, e);
+            return ElementRegistry::default()
+        },
+    }
+}
+
+
 fn get_granule_index(sand_board: &SandBoard, engine: &GraphicsEngine, input: &InputBuffer) -> (f64, f64) {
 
     // add offset x
@@ -389,13 +575,16 @@ fn get_granule_index(sand_board: &SandBoard, engine: &GraphicsEngine, input: &In
     return (x_adj, y_adj)
 }
 
-fn draw_selection_bar(mouse_pos: PhysicalPosition<f64>, clicked: bool, selected: usize, position: (usize, usize), sand_texture: &mut ScreenTexture) -> usize {
+fn draw_selection_bar(mouse_pos: PhysicalPosition<f64>, clicked: bool, selected: usize, colors: &[[u8; 4]], position: (usize, usize), sand_texture: &mut ScreenTexture) -> usize {
     let rect_size = (10, 10);
     let margin = 5;
-    for i in 0..ELEMENT_LIST.len() {
-        let x = position.0 + (i *(rect_size.0) + margin); 
+    // as many swatches to a row as fit across the texture, then another row
+    let per_row = (sand_texture.dimensions.0.saturating_sub(position.0 + margin) / rect_size.0).max(1);
+    for i in 0..colors.len() {
+        let x = position.0 + ((i % per_row) * rect_size.0 + margin);
+        let y = position.1 + (i / per_row) * rect_size.1;
         let is_selected = selected == i;
-        input_box(mouse_pos, clicked, (x as i64, position.1 as i64), is_selected, rect_size, sand_texture, ELEMENT_COLORS[i]);
+        input_box(mouse_pos, clicked, (x as i64, y as i64), is_selected, rect_size, sand_texture, colors[i]);
     }
     return 0;
 }